@@ -1,14 +1,28 @@
 use std::{
 	collections::{HashMap, HashSet},
+	path::{Path, PathBuf},
 	str::FromStr,
 };
 
-use clap::Parser;
-
-use crate::config::*;
+use clap::{Parser, Subcommand};
+use glorious_mouse_control::{
+	config::*,
+	store::ConfigScope,
+	usb::{
+		device::{Device, DeviceSelector},
+		record,
+		Model,
+		Options,
+		PACKET_DPI,
+		TARGET_DEVICES,
+	},
+};
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 pub struct Command {
+	#[clap(subcommand)]
+	pub action: Option<Action>,
+
 	// Lighting
 	/// LED lighting mode
 	#[clap(long, value_parser)]
@@ -86,15 +100,17 @@ pub struct Command {
 	/// Set the color for a DPI setting (<index 0-5>:<hex color>))
 	#[clap(long, value_parser = idx_split_parse::<Color, 0, 5>)]
 	pub dpi_color: Vec<(u8, Color)>,
-	/// Set the X and Y DPI for a DPI setting (<index 0-5>:<dpi ending in 00>)
-	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u8, 0, 5>)]
-	pub dpi: Vec<(u8, u8)>,
-	/// Set the X DPI for a DPI setting (<index 0-5>:<dpi ending in 00>)
-	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u8, 0, 5>)]
-	pub dpi_x: Vec<(u8, u8)>,
-	/// Set the Y DPI for a DPI setting (<index 0-5>:<dpi ending in 00>)
-	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u8, 0, 5>)]
-	pub dpi_y: Vec<(u8, u8)>,
+	/// Set the X and Y DPI for a DPI setting (<index 0-5>:<dpi>). The DPI
+	/// must be a multiple of 100 supported by the mouse (100-12000 for the
+	/// Model O and Model D)
+	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u16, 0, 5>)]
+	pub dpi: Vec<(u8, u16)>,
+	/// Set the X DPI for a DPI setting (<index 0-5>:<dpi>)
+	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u16, 0, 5>)]
+	pub dpi_x: Vec<(u8, u16)>,
+	/// Set the Y DPI for a DPI setting (<index 0-5>:<dpi>)
+	#[clap(long, value_parser = idx_split_parse_cast::<DpiWrapper, u16, 0, 5>)]
+	pub dpi_y: Vec<(u8, u16)>,
 	/// Reset dpis not listed
 	#[clap(long, value_parser)]
 	pub reset_dpis: bool,
@@ -115,24 +131,51 @@ pub struct Command {
 	pub debounce_time: Option<DebounceTime>,
 
 	// Mouse buttons
-	/// Left mouse button action
-	#[clap(long, value_parser)]
-	pub left_button: Option<MouseButtonType>,
-	/// Left mouse button action
-	#[clap(long, value_parser)]
-	pub right_button: Option<MouseButtonType>,
-	/// Left mouse button action
+	/// Left mouse button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub left_button: Option<buttons::ButtonAction>,
+	/// Right mouse button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub right_button: Option<buttons::ButtonAction>,
+	/// Middle mouse button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub middle_button: Option<buttons::ButtonAction>,
+	/// Forward mouse button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub forward_button: Option<buttons::ButtonAction>,
+	/// Back mouse button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub back_button: Option<buttons::ButtonAction>,
+	/// DPI button action (key:<key> binds a key, e.g. key:ctrl+c)
+	#[clap(long, value_parser = ButtonActionParser)]
+	pub dpi_button: Option<buttons::ButtonAction>,
+	/// Set the action of any button (<button>:<action>), where <button> is
+	/// a button name (e.g. left) or slot-<0-18> for any slot of the buttons
+	/// packet
+	#[clap(long, value_parser = button_parser)]
+	pub button: Vec<(String, buttons::ButtonAction)>,
+	/// Give a button its default action again (<button>, as for --button)
 	#[clap(long, value_parser)]
-	pub middle_button: Option<MouseButtonType>,
-	/// Left mouse button action
-	#[clap(long, value_parser)]
-	pub forward_button: Option<MouseButtonType>,
-	/// Left mouse button action
-	#[clap(long, value_parser)]
-	pub back_button: Option<MouseButtonType>,
-	/// Left mouse button action
-	#[clap(long, value_parser)]
-	pub dpi_button: Option<MouseButtonType>,
+	pub reset_button: Vec<String>,
+
+	// Macros
+	/// Store a macro from a file in a macro slot (<slot 0-7>:<file>). Files
+	/// ending in .json are read as JSON, others as text with one event per
+	/// line (see README). Bind it to a button with macro:<slot>
+	#[clap(
+		long = "macro",
+		value_parser = idx_split_parse_cast::<MacroFile, macros::Macro, 0, { macros::MACRO_SLOTS - 1 }>
+	)]
+	pub set_macro: Vec<(u8, macros::Macro)>,
+	/// Remove the macro in a macro slot
+	#[clap(long, value_parser = clap::value_parser!(u8).range(0..macros::MACRO_SLOTS as i64))]
+	pub clear_macro: Vec<u8>,
+
+	// Raw data
+	/// Override an unknown byte of the main packet (<offset>:<hex byte>,
+	/// offsets may be decimal or 0x prefixed hex)
+	#[clap(long, value_parser = raw_byte_parser)]
+	pub raw: Vec<(usize, u8)>,
 
 	// Extra Flags
 	/// Only use flags (ignore config file)
@@ -144,6 +187,139 @@ pub struct Command {
 	/// Set the config file location
 	#[clap(long, value_parser)]
 	pub config: Option<String>,
+	/// Load and save the config per model, port or serial number instead
+	/// of the most specific config that already exists
+	#[clap(long, value_parser, conflicts_with = "config")]
+	pub config_scope: Option<ConfigScope>,
+	/// Start from the configuration currently on the mouse instead of the
+	/// config file (needs --experimental, see `get`)
+	#[clap(long, value_parser)]
+	pub from_device: bool,
+	/// Print the packets that would be sent instead of sending them (no
+	/// mouse needs to be connected, and the config file is not saved)
+	#[clap(long, value_parser, conflicts_with = "from-device")]
+	pub dry_run: bool,
+	/// Model to build the packets for with --dry-run, which decides the
+	/// names of the buttons (model-d or model-o, default model-d)
+	#[clap(long, value_parser = model_parser, requires = "dry-run")]
+	pub model: Option<&'static Model>,
+	/// Record every control transfer sent to or read from the mouse to a
+	/// session file, which can be replayed in tests. With --all and in the
+	/// daemon, each mouse is recorded to its own file named after its port
+	/// path (e.g. session.1-2.jsonl). `recover` is not recorded
+	#[clap(long, global = true, value_parser)]
+	pub record: Option<String>,
+	/// Select the mouse to use (<index>, <bus>:<address>, <bus>-<port path>
+	/// or serial:<serial>, see `list-devices`)
+	#[clap(long, global = true, value_parser = device_selector_parser)]
+	pub device: Option<DeviceSelector>,
+	/// Use every connected mouse
+	#[clap(long, global = true, value_parser, conflicts_with = "device")]
+	pub all: bool,
+	/// Use parts of the protocol which have not been confirmed against the
+	/// official software: reading the configuration from the mouse, binding
+	/// keys and macros to buttons, and storing macros
+	#[clap(long, global = true, value_parser)]
+	pub experimental: bool,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Action {
+	/// Print the configuration currently on the mouse. Experimental (needs
+	/// --experimental): the request used to read it is a guess, sent to the
+	/// same report the debounce time is written to, so it may change the
+	/// debounce time or other settings of the mouse
+	Get,
+	/// List connected mice
+	ListDevices,
+	/// Manage named profiles
+	Profile {
+		#[clap(subcommand)]
+		action: ProfileAction,
+	},
+	/// Manage config files
+	Config {
+		#[clap(subcommand)]
+		action: ConfigAction,
+	},
+	/// Print a field-by-field breakdown of a Type 1, Type 2 or debounce
+	/// report
+	Dissect {
+		/// Hex bytes of the report, or a file containing the raw report
+		input: String,
+	},
+	/// Find the reports sent to supported mice in a usbmon pcap/pcapng
+	/// capture and decode them
+	ImportCapture {
+		/// Capture file
+		file: String,
+		/// Print the fields each report changes instead of the whole config
+		#[clap(long, value_parser)]
+		diff: bool,
+		/// Save the last reports sent to the mouse as a golden test fixture
+		/// in this directory (e.g. tests/fixtures/<name>)
+		#[clap(long, value_parser)]
+		write_fixture: Option<String>,
+	},
+	/// Reattach the kernel drivers of a mouse left without them (e.g. one
+	/// that stopped responding after the program was killed). This is not
+	/// recorded by --record
+	Recover,
+	/// Stay running and apply the saved config whenever a mouse is connected
+	Daemon {
+		/// How long a mouse must stay connected before its config is applied
+		/// (milliseconds)
+		#[clap(long, value_parser, default_value_t = 1000)]
+		settle_ms: u64,
+	},
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ProfileAction {
+	/// List saved profiles (the active profile is marked with `*`)
+	List,
+	/// Print a profile
+	Show { name: String },
+	/// Save a new profile from the configuration flags (and `--from-device`)
+	Create {
+		name: String,
+		/// Replace the profile if it already exists
+		#[clap(long, value_parser)]
+		force: bool,
+	},
+	/// Copy a profile
+	Copy {
+		from: String,
+		to: String,
+		/// Replace the destination profile if it already exists
+		#[clap(long, value_parser)]
+		force: bool,
+	},
+	/// Delete a profile
+	Delete { name: String },
+	/// Apply a profile and make it the active profile. While a profile is
+	/// active, changes are loaded from and saved to it
+	Switch { name: String },
+	/// Stop using the active profile
+	Deactivate,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigAction {
+	/// Upgrade config files written by older versions (every config file, or
+	/// just the one given with `--config`). The old files are kept as
+	/// `<name>.v<version>.bak`
+	Migrate {
+		/// Only print what would change
+		#[clap(long, value_parser)]
+		check: bool,
+	},
+	/// Report every mistake in the config files (or just the one given with
+	/// `--config`) without changing them
+	Check,
+	/// Print a JSON Schema describing config files, for editors to
+	/// autocomplete and validate them with
+	Schema,
 }
 
 /// Extra CLI params that don't go in the config file
@@ -151,32 +327,78 @@ pub struct ExtraFlags<'c> {
 	pub save_config: bool,
 	pub use_config: bool,
 	pub config_location: Option<&'c str>,
+	pub config_scope: Option<ConfigScope>,
+	pub from_device: bool,
+	pub dry_run: bool,
+	pub dry_run_model: &'static Model,
+	pub record: Option<&'c Path>,
+	/// Whether each device is recorded to its own session file (see
+	/// [`record::device_session_path`])
+	pub per_device_sessions: bool,
+	pub device: Option<&'c DeviceSelector>,
+	pub all: bool,
+	pub experimental: bool,
 }
 
-/// Newtype struct used to format a polling rate value
-struct DpiWrapper(u8);
+impl ExtraFlags<'_> {
+	/// Session file transfers with `device` are recorded to, if any
+	pub fn session_path(&self, device: &Device) -> Option<PathBuf> {
+		self.record.map(|path| match self.per_device_sessions {
+			true => record::device_session_path(path, &device.port_path()),
+			false => path.to_owned(),
+		})
+	}
+
+	/// Options for talking to `device`
+	pub fn options(&self, device: &Device) -> Options {
+		Options {
+			record: self.session_path(device),
+			experimental: self.experimental,
+		}
+	}
+}
+
+/// Newtype struct used to parse a DPI value. Only values which fit in a
+/// packet are accepted, the limits of each model are checked when the
+/// config is applied.
+struct DpiWrapper(u16);
 
 impl TryFrom<&str> for DpiWrapper {
 	type Error = String;
 
 	fn try_from(value: &str) -> Result<Self, Self::Error> {
-		if !value.ends_with("00") {
-			Err(r#"dpi did not end in "00""#.to_owned())
-		} else {
-			Ok(Self(
-				u8::from_str(&value[..value.len() - 2])
-					.map_err(|_| "dpi was not between 100 and 25500")?,
-			))
-		}
+		let dpi = u16::from_str(value).map_err(|_| format!("`{value}` is not a DPI value"))?;
+		PACKET_DPI.check(dpi, "the protocol")?;
+		Ok(Self(dpi))
 	}
 }
 
-impl From<DpiWrapper> for u8 {
+impl From<DpiWrapper> for u16 {
 	fn from(dpi: DpiWrapper) -> Self {
 		dpi.0
 	}
 }
 
+/// Macro loaded from the file at the path it is parsed from (see
+/// [`macros::Macro::load`]).
+struct MacroFile(macros::Macro);
+
+impl TryFrom<&str> for MacroFile {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		macros::Macro::load(Path::new(value))
+			.map(Self)
+			.map_err(|e| e.to_string())
+	}
+}
+
+impl From<MacroFile> for macros::Macro {
+	fn from(file: MacroFile) -> Self {
+		file.0
+	}
+}
+
 /// Function to parse a color from a hex string, returns
 /// the value of [`TryFrom`]`<&str>` for [`Color`].
 ///
@@ -187,6 +409,108 @@ fn color_parser(s: &str) -> Result<Color, String> {
 	TryFrom::try_from(s)
 }
 
+/// Parses a [`buttons::ButtonAction`], listing the builtin actions in the
+/// help.
+#[derive(Clone)]
+struct ButtonActionParser;
+
+impl clap::builder::TypedValueParser for ButtonActionParser {
+	type Value = buttons::ButtonAction;
+
+	fn parse_ref(
+		&self,
+		cmd: &clap::Command,
+		arg: Option<&clap::Arg>,
+		value: &std::ffi::OsStr,
+	) -> Result<Self::Value, clap::Error> {
+		let parse = |s: &str| buttons::ButtonAction::try_from(s);
+		parse.parse_ref(cmd, arg, value)
+	}
+
+	fn possible_values(
+		&self,
+	) -> Option<Box<dyn Iterator<Item = clap::PossibleValue<'static>> + '_>> {
+		Some(Box::new(
+			<MouseButtonType as clap::ArgEnum>::value_variants()
+				.iter()
+				.filter_map(clap::ArgEnum::to_possible_value)
+				.chain([clap::PossibleValue::new("key:<key>")]),
+		))
+	}
+}
+
+/// Parse a string in the form `<button>:<action>` into a button name and
+/// [`buttons::ButtonAction`]. Button names are checked against the model
+/// when the config is applied.
+///
+/// # Errors
+///
+/// If the string is not in the expected form, or `<action>` is not a valid
+/// action.
+fn button_parser(s: &str) -> Result<(String, buttons::ButtonAction), String> {
+	let (button, action) = s
+		.split_once(':')
+		.ok_or_else(|| r#"must be in the form "<button>:<action>""#.to_owned())?;
+
+	Ok((button.to_owned(), buttons::ButtonAction::try_from(action)?))
+}
+
+/// Finds the [`Model`] with the key `s` (as used in config file names).
+///
+/// # Errors
+///
+/// If no model has that key.
+fn model_parser(s: &str) -> Result<&'static Model, String> {
+	TARGET_DEVICES
+		.iter()
+		.find(|model| model.key == s)
+		.ok_or_else(|| {
+			let keys = TARGET_DEVICES
+				.iter()
+				.map(|model| model.key)
+				.collect::<Vec<_>>();
+			format!("unknown model `{s}` (expected one of {})", keys.join(", "))
+		})
+}
+
+/// Function to parse a device selector, returns the value of
+/// [`TryFrom`]`<&str>` for [`DeviceSelector`].
+///
+/// # Errors
+///
+/// If the string is not a valid device selector
+fn device_selector_parser(s: &str) -> Result<DeviceSelector, String> {
+	TryFrom::try_from(s)
+}
+
+/// Parse a string in the form `<offset>:<hex byte>` into a main packet
+/// offset and byte value.
+///
+/// # Errors
+///
+/// If the string is not in the expected form, or `<offset>` is not part of
+/// a raw block (see [`RawMainPacket`]).
+fn raw_byte_parser(s: &str) -> Result<(usize, u8), String> {
+	let (offset_str, value_str) = s
+		.split_once(':')
+		.ok_or_else(|| r#"must be in the form "<offset>:<hex byte>""#.to_owned())?;
+
+	let offset = match offset_str.strip_prefix("0x") {
+		Some(hex) => usize::from_str_radix(hex, 16),
+		None => usize::from_str(offset_str),
+	}
+	.map_err(|_| format!("could not parse `{offset_str}` as an offset"))?;
+
+	if RawMainPacket::default().byte_mut(offset).is_none() {
+		return Err(format!("offset {offset} is not an unknown byte of the main packet"))
+	}
+
+	let value = u8::from_str_radix(value_str, 16)
+		.map_err(|_| format!("could not parse `{value_str}` as a hex byte"))?;
+
+	Ok((offset, value))
+}
+
 /// Split a string in the form `<index>:<value>` into a `u8` index
 /// and `T` value.
 ///
@@ -264,7 +588,16 @@ impl Command {
 		ExtraFlags {
 			save_config: !self.nosave,
 			use_config: !self.noconf,
-			config_location: self.config.as_ref().map(|s| &**s),
+			config_location: self.config.as_deref(),
+			config_scope: self.config_scope,
+			from_device: self.from_device,
+			dry_run: self.dry_run,
+			dry_run_model: self.model.unwrap_or(&TARGET_DEVICES[0]),
+			record: self.record.as_deref().map(Path::new),
+			per_device_sessions: self.all || matches!(self.action, Some(Action::Daemon { .. })),
+			device: self.device.as_ref(),
+			all: self.all,
+			experimental: self.experimental,
 		}
 	}
 
@@ -272,6 +605,7 @@ impl Command {
 	/// returning the [`Config`] struct.
 	pub fn apply_command_config(self, config: Config) -> Config {
 		Config {
+			version: config.version,
 			lighting: lighting::Lighting {
 				mode: self.mode.unwrap_or(config.lighting.mode),
 				solid: lighting::Solid {
@@ -381,9 +715,9 @@ impl Command {
 
 				let mut dpi_color_overrides =
 					self.dpi_color.into_iter().collect::<HashMap<u8, Color>>();
-				let base_dpi_overrides = self.dpi.into_iter().collect::<HashMap<u8, u8>>();
-				let x_dpi_overrides = self.dpi_x.into_iter().collect::<HashMap<u8, u8>>();
-				let y_dpi_overrides = self.dpi_y.into_iter().collect::<HashMap<u8, u8>>();
+				let base_dpi_overrides = self.dpi.into_iter().collect::<HashMap<u8, u16>>();
+				let x_dpi_overrides = self.dpi_x.into_iter().collect::<HashMap<u8, u16>>();
+				let y_dpi_overrides = self.dpi_y.into_iter().collect::<HashMap<u8, u16>>();
 
 				let dpi_overrides = (0..=5)
 					.map(|i| {
@@ -423,13 +757,42 @@ impl Command {
 			polling_rate: self.polling_rate.unwrap_or(config.polling_rate),
 			liftoff_distance: self.liftoff_distance.unwrap_or(config.liftoff_distance),
 			debounce_time: self.debounce_time.unwrap_or(config.debounce_time),
-			buttons: MouseButtons {
-				left: self.left_button.unwrap_or(config.buttons.left),
-				right: self.right_button.unwrap_or(config.buttons.right),
-				middle: self.middle_button.unwrap_or(config.buttons.middle),
-				forward: self.forward_button.unwrap_or(config.buttons.forward),
-				back: self.back_button.unwrap_or(config.buttons.back),
-				dpi: self.dpi_button.unwrap_or(config.buttons.dpi),
+			buttons: {
+				let mut buttons = config.buttons;
+				for (name, action) in [
+					("left", self.left_button),
+					("right", self.right_button),
+					("middle", self.middle_button),
+					("forward", self.forward_button),
+					("back", self.back_button),
+					("dpi", self.dpi_button),
+				] {
+					if let Some(action) = action {
+						buttons.0.insert(name.to_owned(), action);
+					}
+				}
+				buttons.0.extend(self.button);
+				for name in self.reset_button {
+					buttons.0.remove(&name);
+				}
+				buttons
+			},
+			macros: {
+				let mut macros = config.macros;
+				macros.extend(self.set_macro);
+				for slot in self.clear_macro {
+					macros.remove(&slot);
+				}
+				macros
+			},
+			software_buttons: config.software_buttons,
+			raw: {
+				let mut raw = config.raw;
+				for (offset, value) in self.raw {
+					// offsets are checked by `raw_byte_parser`
+					*raw.byte_mut(offset).unwrap() = value;
+				}
+				raw
 			},
 		}
 	}
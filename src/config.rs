@@ -1,12 +1,23 @@
 use std::{
+	collections::BTreeMap,
 	error::Error,
 	fmt::{self, Formatter},
 	num::ParseIntError,
 	ops::Deref,
 };
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use self::buttons::ButtonAction;
+
+pub mod buttons;
+pub mod macros;
+pub mod migrate;
+pub mod schema;
+pub mod software;
+pub mod validate;
+
 #[derive(Debug)]
 pub struct FormatError(pub String);
 
@@ -99,12 +110,118 @@ impl<const MIN: u8, const MAX: u8> From<RangedByte<MIN, MAX>> for RangedByteSeri
 	}
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct HexBytesSerializer(String);
+
+/// Fixed length byte string, stored as space separated hex bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "HexBytesSerializer")]
+#[serde(into = "HexBytesSerializer")]
+pub struct HexBytes<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> TryFrom<&str> for HexBytes<N> {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		let digits = value
+			.chars()
+			.filter(|c| !c.is_whitespace())
+			.collect::<String>();
+
+		if digits.len() != N * 2 || !digits.is_ascii() {
+			return Err(format!("could not parse `{value}` as {N} hex bytes"))
+		}
+
+		let mut bytes = [0u8; N];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+				.map_err(|_| format!("could not parse `{value}` as {N} hex bytes"))?;
+		}
+
+		Ok(Self(bytes))
+	}
+}
+
+impl<const N: usize> TryFrom<HexBytesSerializer> for HexBytes<N> {
+	type Error = FormatError;
+
+	fn try_from(value: HexBytesSerializer) -> Result<Self, Self::Error> {
+		TryFrom::try_from(&value.0 as &str).map_err(FormatError)
+	}
+}
+
+impl<const N: usize> From<HexBytes<N>> for HexBytesSerializer {
+	fn from(bytes: HexBytes<N>) -> Self {
+		HexBytesSerializer(
+			bytes
+				.0
+				.iter()
+				.map(|byte| format!("{byte:02x}"))
+				.collect::<Vec<_>>()
+				.join(" "),
+		)
+	}
+}
+
+/// Finds the variant of a fieldless enum whose discriminant (as returned
+/// by `discriminant`) matches `value`.
+pub fn from_discriminant<T, D>(value: D, discriminant: fn(T) -> D) -> Option<T>
+where
+	T: clap::ArgEnum + Copy,
+	D: PartialEq,
+{
+	T::value_variants()
+		.iter()
+		.copied()
+		.find(|variant| discriminant(*variant) == value)
+}
+
+/// Lists the values which differ between two JSON representations of a
+/// config as `(<dotted path>, <old value>, <new value>)`.
+pub fn json_diff(
+	old: &serde_json::Value,
+	new: &serde_json::Value,
+) -> Vec<(String, serde_json::Value, serde_json::Value)> {
+	use serde_json::Value;
+
+	fn diff_into(
+		path: String,
+		old: &Value,
+		new: &Value,
+		changes: &mut Vec<(String, Value, Value)>,
+	) {
+		let join = |key: &dyn fmt::Display| match path.is_empty() {
+			true => key.to_string(),
+			false => format!("{path}.{key}"),
+		};
+
+		match (old, new) {
+			(Value::Object(old), Value::Object(new)) =>
+				for (key, new_value) in new {
+					diff_into(join(key), old.get(key).unwrap_or(&Value::Null), new_value, changes);
+				},
+			(Value::Array(old), Value::Array(new)) if old.len() == new.len() =>
+				for (i, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+					diff_into(join(&i), old_value, new_value, changes);
+				},
+			_ if old != new => changes.push((path, old.clone(), new.clone())),
+			_ => {}
+		}
+	}
+
+	let mut changes = Vec::new();
+	diff_into(String::new(), old, new, &mut changes);
+	changes
+}
+
 pub mod lighting {
+	use schemars::JsonSchema;
 	use serde::{Deserialize, Serialize};
 
 	use super::{Color, RangedByte};
 
-	#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, clap::ArgEnum)]
 	pub enum Mode {
 		Off = 0x00,
 		Rainbow = 0x01,
@@ -119,13 +236,13 @@ pub mod lighting {
 		BreathingSingle = 0x0a,
 	}
 
-	#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, clap::ArgEnum)]
 	pub enum RainbowDirection {
 		Backward = 0x00,
 		Forward = 0x01,
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Rainbow {
 		pub speed: RangedByte<1, 3>,
 		pub direction: RainbowDirection,
@@ -140,7 +257,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Solid {
 		pub brightness: RangedByte<1, 4>,
 		pub color: Color,
@@ -159,7 +276,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Breathing {
 		pub brightness: RangedByte<1, 4>,
 		pub speed: RangedByte<1, 3>,
@@ -212,7 +329,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Tail {
 		pub brightness: RangedByte<1, 4>,
 		pub speed: RangedByte<1, 3>,
@@ -227,7 +344,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Fade {
 		pub speed: RangedByte<1, 3>,
 	}
@@ -240,7 +357,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Rave {
 		pub brightness: RangedByte<1, 4>,
 		pub speed: RangedByte<1, 3>,
@@ -268,7 +385,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Wave {
 		pub brightness: RangedByte<1, 4>,
 		pub speed: RangedByte<1, 3>,
@@ -283,7 +400,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct BreathingSingle {
 		pub speed: RangedByte<1, 3>,
 		pub color: Color,
@@ -302,7 +419,7 @@ pub mod lighting {
 		}
 	}
 
-	#[derive(Serialize, Deserialize, Debug)]
+	#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 	pub struct Lighting {
 		pub mode: Mode,
 		pub solid: Solid,
@@ -332,17 +449,25 @@ pub mod lighting {
 	}
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 pub struct Dpi {
 	pub enable: bool,
 	// TODO: grab defaults from windows software
 	pub color: Color,
-	// TODO: check if these needs to have a bounded range w/ dpi cap
-	pub x_dpi: u8,
-	pub y_dpi: u8,
+	/// DPI, limited by the model (see [`crate::usb::Model::dpi`])
+	#[schemars(
+		schema_with = "schema::dpi_schema",
+		description = "DPI, in steps of 100"
+	)]
+	pub x_dpi: u16,
+	#[schemars(
+		schema_with = "schema::dpi_schema",
+		description = "DPI, in steps of 100"
+	)]
+	pub y_dpi: u16,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, clap::ArgEnum)]
 pub enum PollingRate {
 	#[serde(rename = "125hz")]
 	_125hz = 0x01,
@@ -354,7 +479,7 @@ pub enum PollingRate {
 	_1000hz = 0x04,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, clap::ArgEnum)]
 pub enum LiftoffDistance {
 	#[serde(rename = "2mm")]
 	_2mm = 0x01,
@@ -362,7 +487,9 @@ pub enum LiftoffDistance {
 	_3mm = 0x02,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+/// Fixed button actions (see [`buttons::ButtonAction`]), by their code in
+/// the `Type 2` packet.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
 pub enum MouseButtonType {
 	Disable = 0x50010000,
 	LeftClick = 0x11010000,
@@ -372,35 +499,53 @@ pub enum MouseButtonType {
 	Forward = 0x11100000,
 	ScrollUp = 0x12010000,
 	ScrollDown = 0x12ff0000,
+	ThreeClick = 0x31013203,
 	DpiLoop = 0x41000000,
 	DpiPlus = 0x41010000,
 	DpiMinus = 0x41020000,
+	MediaPlayer = 0x22000100,
+	MediaPlayPause = 0x22080000,
+	MediaNext = 0x22010000,
+	MediaPrevious = 0x22020000,
+	MediaStop = 0x22040000,
+	MediaMute = 0x22100000,
+	VolumeUp = 0x22400000,
+	VolumeDown = 0x22800000,
+	Email = 0x22001000,
+	Calculator = 0x22002000,
+	Explorer = 0x22000200,
+	HomePage = 0x22000002,
+	#[clap(name = "dpi-lock-400")]
+	DpiLock400 = 0x42030000,
+	#[clap(name = "dpi-lock-500")]
+	DpiLock500 = 0x42040000,
+	#[clap(name = "dpi-lock-600")]
+	DpiLock600 = 0x42050000,
+	#[clap(name = "dpi-lock-700")]
+	DpiLock700 = 0x42060000,
+	#[clap(name = "dpi-lock-800")]
+	DpiLock800 = 0x42070000,
+	#[clap(name = "dpi-lock-900")]
+	DpiLock900 = 0x42080000,
+	#[clap(name = "dpi-lock-1000")]
+	DpiLock1000 = 0x42090000,
+	#[clap(name = "dpi-lock-1100")]
+	DpiLock1100 = 0x420a0000,
+	#[clap(name = "dpi-lock-1200")]
+	DpiLock1200 = 0x420b0000,
+	#[clap(name = "dpi-lock-1300")]
+	DpiLock1300 = 0x420c0000,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct MouseButtons {
-	pub left: MouseButtonType,
-	pub right: MouseButtonType,
-	pub middle: MouseButtonType,
-	pub forward: MouseButtonType,
-	pub back: MouseButtonType,
-	pub dpi: MouseButtonType,
-}
-
-impl Default for MouseButtons {
-	fn default() -> Self {
-		Self {
-			left: MouseButtonType::LeftClick,
-			right: MouseButtonType::RightClick,
-			middle: MouseButtonType::MiddleClick,
-			forward: MouseButtonType::Forward,
-			back: MouseButtonType::Back,
-			dpi: MouseButtonType::DpiLoop,
-		}
-	}
-}
+/// Button actions by button name: one of the buttons of the model (e.g.
+/// `left` or `dpi`, see [`crate::usb::Model::buttons`]), or `slot-<n>` for
+/// any slot of the `Type 2` packet. Buttons which are not listed keep the
+/// model's default action.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default)]
+#[serde(transparent)]
+pub struct MouseButtons(pub BTreeMap<String, ButtonAction>);
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, clap::ArgEnum)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, clap::ArgEnum)]
 pub enum DebounceTime {
 	#[serde(rename = "4ms")]
 	_4ms = 0x02,
@@ -418,9 +563,70 @@ pub enum DebounceTime {
 	_16ms = 0x08,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Bytes of the `Type 1` main packet whose meaning is unknown (see
+/// `packet_spec.md`). These are read from the device and written back
+/// verbatim, so newly discovered fields can be experimented with by editing
+/// them.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[serde(default)]
+pub struct RawMainPacket {
+	/// Offset `2`, following the report id and command bytes (`04 11`)
+	pub header: HexBytes<8>,
+	/// Offset `25`, between the DPI values and DPI colors
+	pub after_dpi: HexBytes<4>,
+	/// Offset `47`, between the DPI colors and LED mode
+	pub after_dpi_colors: HexBytes<6>,
+	/// Offset `60`, between the solid and breathing colors
+	pub before_breathing: HexBytes<2>,
+	/// Offset `85`, between the fade speed and rave settings
+	pub after_fade: HexBytes<31>,
+	/// Offset `123`, between the rave and wave settings
+	pub before_wave: HexBytes<1>,
+}
+
+impl RawMainPacket {
+	/// Gets a reference to the byte at `offset` in the main packet, if it
+	/// falls within one of the raw blocks.
+	pub fn byte_mut(&mut self, offset: usize) -> Option<&mut u8> {
+		let blocks: [(usize, &mut [u8]); 6] = [
+			(2, &mut self.header.0),
+			(25, &mut self.after_dpi.0),
+			(47, &mut self.after_dpi_colors.0),
+			(60, &mut self.before_breathing.0),
+			(85, &mut self.after_fade.0),
+			(123, &mut self.before_wave.0),
+		];
+
+		blocks
+			.into_iter()
+			.find(|(start, block)| (*start..*start + block.len()).contains(&offset))
+			.map(|(start, block)| &mut block[offset - start])
+	}
+}
+
+impl Default for RawMainPacket {
+	#[rustfmt::skip]
+	fn default() -> Self {
+		Self {
+			header: HexBytes([0x00, 0x7b, 0x00, 0x00, 0x00, 0x00, 0x64, 0x06]),
+			after_dpi: HexBytes([0x00; 4]),
+			after_dpi_colors: HexBytes([0x00; 6]),
+			before_breathing: HexBytes([0x42, 0x07]),
+			after_fade: HexBytes([
+				                                                       0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00,
+				0x00, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff,  0xff, 0xff, 0xff, 0xff, 0xfa, 0x00, 0xff, 0xff,
+				0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00,
+			]),
+			before_wave: HexBytes([0x02]),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
 #[serde(default)]
 pub struct Config {
+	/// Version of the config format (see [`migrate`])
+	pub version: u32,
 	pub lighting: lighting::Lighting,
 	pub dpi: [Dpi; 6],
 	pub current_dpi: RangedByte<0, 5>,
@@ -428,11 +634,47 @@ pub struct Config {
 	pub liftoff_distance: LiftoffDistance,
 	pub debounce_time: DebounceTime,
 	pub buttons: MouseButtons,
+	/// Macros by slot (see [`macros::MACRO_SLOTS`])
+	pub macros: BTreeMap<u8, macros::Macro>,
+	/// Buttons handled by the daemon instead of the mouse, by button name
+	/// (see [`software`])
+	pub software_buttons: BTreeMap<String, software::SoftwareAction>,
+	pub raw: RawMainPacket,
+}
+
+impl Config {
+	/// Checks that the config can be applied, fixing what can be fixed (a
+	/// selected DPI which is not enabled is replaced by the first enabled
+	/// DPI).
+	///
+	/// # Errors
+	///
+	/// If [`Config::validate`] finds any other problem.
+	pub fn check(&mut self) -> Result<(), crate::error::Error> {
+		let first_enabled = self.dpi.iter().position(|Dpi { enable, .. }| *enable);
+		let selected = self.dpi.get(*self.current_dpi as usize);
+
+		// check that the selected dpi is enabled and reset if it isn't
+		if let (Some(first_enabled), Some(Dpi { enable: false, .. })) = (first_enabled, selected) {
+			self.current_dpi = RangedByte(first_enabled as u8);
+
+			eprintln!(
+				"warning: the selected DPI is not enabled - DPI {} has been selected instead",
+				*self.current_dpi
+			);
+		}
+
+		match self.validate() {
+			problems if problems.is_empty() => Ok(()),
+			problems => Err(crate::error::Error::Validation(problems)),
+		}
+	}
 }
 
 impl Default for Config {
 	fn default() -> Self {
 		Self {
+			version: migrate::CURRENT_VERSION,
 			lighting: lighting::Lighting::default(),
 			dpi: [
 				Dpi {
@@ -442,8 +684,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 4,
-					y_dpi: 4,
+					x_dpi: 400,
+					y_dpi: 400,
 				},
 				Dpi {
 					enable: true,
@@ -452,8 +694,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 8,
-					y_dpi: 8,
+					x_dpi: 800,
+					y_dpi: 800,
 				},
 				Dpi {
 					enable: true,
@@ -462,8 +704,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 12,
-					y_dpi: 12,
+					x_dpi: 1200,
+					y_dpi: 1200,
 				},
 				Dpi {
 					enable: false,
@@ -472,8 +714,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 14,
-					y_dpi: 14,
+					x_dpi: 1400,
+					y_dpi: 1400,
 				},
 				Dpi {
 					enable: false,
@@ -482,8 +724,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 16,
-					y_dpi: 16,
+					x_dpi: 1600,
+					y_dpi: 1600,
 				},
 				Dpi {
 					enable: false,
@@ -492,8 +734,8 @@ impl Default for Config {
 						g: 255,
 						b: 255,
 					},
-					x_dpi: 18,
-					y_dpi: 18,
+					x_dpi: 1800,
+					y_dpi: 1800,
 				},
 			],
 			current_dpi: RangedByte(0),
@@ -501,6 +743,9 @@ impl Default for Config {
 			liftoff_distance: LiftoffDistance::_2mm,
 			debounce_time: DebounceTime::_10ms,
 			buttons: MouseButtons::default(),
+			macros: BTreeMap::new(),
+			software_buttons: BTreeMap::new(),
+			raw: RawMainPacket::default(),
 		}
 	}
 }
@@ -0,0 +1,375 @@
+use std::fmt::{self, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use super::{from_discriminant, FormatError, MouseButtonType};
+
+/// Category byte of keyboard key actions. It is followed by the modifier
+/// bitset, the HID usage id of the key and a zero byte. Not confirmed
+/// against the official software (see [`ButtonAction::is_unconfirmed`]).
+const KEY_CATEGORY: u8 = 0x21;
+/// Category byte of macro actions. It is followed by the macro slot and two
+/// zero bytes. Not confirmed against the official software.
+const MACRO_CATEGORY: u8 = 0x70;
+
+/// Modifier names, by their bit in the HID modifier bitset.
+pub(super) const MODIFIERS: [&str; 8] = [
+	"ctrl", "shift", "alt", "super", "rctrl", "rshift", "ralt", "rsuper",
+];
+
+/// Names of keys which are not letters, digits or function keys, by their
+/// HID usage id.
+const NAMED_KEYS: &[(&str, u8)] = &[
+	("enter", 0x28),
+	("esc", 0x29),
+	("backspace", 0x2a),
+	("tab", 0x2b),
+	("space", 0x2c),
+	("minus", 0x2d),
+	("equal", 0x2e),
+	("leftbrace", 0x2f),
+	("rightbrace", 0x30),
+	("backslash", 0x31),
+	("semicolon", 0x33),
+	("apostrophe", 0x34),
+	("grave", 0x35),
+	("comma", 0x36),
+	("dot", 0x37),
+	("slash", 0x38),
+	("capslock", 0x39),
+	("printscreen", 0x46),
+	("scrolllock", 0x47),
+	("pause", 0x48),
+	("insert", 0x49),
+	("home", 0x4a),
+	("pageup", 0x4b),
+	("delete", 0x4c),
+	("end", 0x4d),
+	("pagedown", 0x4e),
+	("right", 0x4f),
+	("left", 0x50),
+	("down", 0x51),
+	("up", 0x52),
+	("numlock", 0x53),
+	("menu", 0x65),
+];
+
+/// HID usage id of the key named `name` (see [`key_name`]). Any key can
+/// also be given by its usage id as `0x<hex>`.
+pub(super) fn key_usage(name: &str) -> Option<u8> {
+	let name = name.to_ascii_lowercase();
+
+	match name.as_bytes() {
+		[c @ b'a'..=b'z'] => return Some(0x04 + c - b'a'),
+		[c @ b'1'..=b'9'] => return Some(0x1e + c - b'1'),
+		[b'0'] => return Some(0x27),
+		_ => {}
+	}
+
+	if let Some(hex) = name.strip_prefix("0x") {
+		return u8::from_str_radix(hex, 16).ok().filter(|usage| *usage != 0)
+	}
+
+	if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+		return match n {
+			1..=12 => Some(0x3a + n - 1),
+			13..=24 => Some(0x68 + n - 13),
+			_ => None,
+		}
+	}
+
+	NAMED_KEYS
+		.iter()
+		.find(|(key, _)| *key == name)
+		.map(|(_, usage)| *usage)
+}
+
+/// Name of the key with HID usage id `usage`, as accepted by [`key_usage`].
+pub(super) fn key_name(usage: u8) -> String {
+	match usage {
+		0x04..=0x1d => ((b'a' + usage - 0x04) as char).to_string(),
+		0x1e..=0x26 => ((b'1' + usage - 0x1e) as char).to_string(),
+		0x27 => "0".to_owned(),
+		0x3a..=0x45 => format!("f{}", usage - 0x3a + 1),
+		0x68..=0x73 => format!("f{}", usage - 0x68 + 13),
+		_ => NAMED_KEYS
+			.iter()
+			.find(|(_, key)| *key == usage)
+			.map(|(name, _)| (*name).to_owned())
+			.unwrap_or_else(|| format!("0x{usage:02x}")),
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct KeyComboSerializer(String);
+
+/// A keyboard key pressed together with modifiers, written as
+/// `<modifier>+...+<key>` (e.g. `ctrl+shift+t`). Either the modifiers or the
+/// key may be left out.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "KeyComboSerializer")]
+#[serde(into = "KeyComboSerializer")]
+pub struct KeyCombo {
+	/// HID modifier bitset (see [`MODIFIERS`])
+	pub modifiers: u8,
+	/// HID usage id of the key, or `0` for modifiers only
+	pub key: u8,
+}
+
+impl TryFrom<&str> for KeyCombo {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		let mut combo = Self {
+			modifiers: 0,
+			key: 0,
+		};
+		let mut parts = value.split('+').peekable();
+
+		while let Some(part) = parts.next() {
+			let modifier = MODIFIERS
+				.iter()
+				.position(|modifier| modifier.eq_ignore_ascii_case(part));
+
+			match (modifier, parts.peek()) {
+				(Some(bit), _) => combo.modifiers |= 1 << bit,
+				(None, None) =>
+					combo.key = key_usage(part).ok_or_else(|| format!("unknown key `{part}`"))?,
+				(None, Some(_)) =>
+					return Err(format!(
+						"unknown modifier `{part}` (expected one of {})",
+						MODIFIERS.join(", ")
+					)),
+			}
+		}
+
+		if combo.modifiers == 0 && combo.key == 0 {
+			return Err("no key given".to_owned())
+		}
+
+		Ok(combo)
+	}
+}
+
+impl fmt::Display for KeyCombo {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let mut parts = MODIFIERS
+			.iter()
+			.enumerate()
+			.filter(|(bit, _)| self.modifiers & (1 << bit) != 0)
+			.map(|(_, modifier)| (*modifier).to_owned())
+			.collect::<Vec<_>>();
+		if self.key != 0 {
+			parts.push(key_name(self.key));
+		}
+
+		write!(f, "{}", parts.join("+"))
+	}
+}
+
+impl TryFrom<KeyComboSerializer> for KeyCombo {
+	type Error = FormatError;
+
+	fn try_from(value: KeyComboSerializer) -> Result<Self, Self::Error> {
+		TryFrom::try_from(&value.0 as &str).map_err(FormatError)
+	}
+}
+
+impl From<KeyCombo> for KeyComboSerializer {
+	fn from(combo: KeyCombo) -> Self {
+		KeyComboSerializer(combo.to_string())
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ButtonActionSerializer {
+	Builtin(MouseButtonType),
+	Other(String),
+}
+
+/// Action of a mouse button, encoded in the `Type 2` packet as a category
+/// byte followed by 3 bytes of parameters.
+///
+/// In config files, builtin actions are written by name (e.g. `LeftClick`),
+/// keys as `key:<key combo>` (see [`KeyCombo`]) and macros as
+/// `macro:<slot>`. On the command line builtin actions are written in kebab
+/// case (e.g. `left-click`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "ButtonActionSerializer")]
+#[serde(into = "ButtonActionSerializer")]
+pub enum ButtonAction {
+	/// Clicks, scrolling, DPI, media and launcher actions
+	Builtin(MouseButtonType),
+	/// A keyboard key, optionally with modifiers
+	Key(KeyCombo),
+	/// Plays the macro stored in a slot (see [`super::macros`])
+	Macro(u8),
+}
+
+impl ButtonAction {
+	/// The 4 byte code of the action in the `Type 2` packet.
+	pub fn code(self) -> [u8; 4] {
+		match self {
+			Self::Builtin(button) => u32::to_be_bytes(button as u32),
+			Self::Key(KeyCombo { modifiers, key }) => [KEY_CATEGORY, modifiers, key, 0x00],
+			Self::Macro(slot) => [MACRO_CATEGORY, slot, 0x00, 0x00],
+		}
+	}
+
+	/// Whether the code of the action is a guess which has not been confirmed
+	/// against the official software (keys and macros).
+	pub fn is_unconfirmed(self) -> bool {
+		matches!(self, Self::Key(_) | Self::Macro(_))
+	}
+
+	/// Finds the action with the 4 byte code `code` (see
+	/// [`ButtonAction::code`]).
+	pub fn from_code(code: [u8; 4]) -> Option<Self> {
+		match code {
+			[KEY_CATEGORY, modifiers, key, 0x00] if modifiers != 0 || key != 0 =>
+				Some(Self::Key(KeyCombo { modifiers, key })),
+			[MACRO_CATEGORY, slot, 0x00, 0x00] => Some(Self::Macro(slot)),
+			_ => from_discriminant(u32::from_be_bytes(code), |b: MouseButtonType| b as u32)
+				.map(Self::Builtin),
+		}
+	}
+}
+
+impl From<MouseButtonType> for ButtonAction {
+	fn from(button: MouseButtonType) -> Self {
+		Self::Builtin(button)
+	}
+}
+
+/// Parses the actions which are written the same way in config files and
+/// on the command line (keys and macros).
+fn parse_prefixed_action(value: &str) -> Option<Result<ButtonAction, String>> {
+	if let Some(combo) = value.strip_prefix("key:") {
+		return Some(KeyCombo::try_from(combo).map(ButtonAction::Key))
+	}
+
+	value.strip_prefix("macro:").map(|slot| {
+		slot.parse::<u8>()
+			.map(ButtonAction::Macro)
+			.map_err(|_| format!("`{slot}` is not a macro slot"))
+	})
+}
+
+impl TryFrom<&str> for ButtonAction {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		if let Some(action) = parse_prefixed_action(value) {
+			return action
+		}
+
+		<MouseButtonType as clap::ArgEnum>::from_str(value, true)
+			.map(Self::Builtin)
+			.map_err(|_| {
+				format!(
+					"could not parse `{value}` as a button action (expected an action such as \
+					 `left-click` or `media-next`, key:<key> such as `key:ctrl+c`, or \
+					 macro:<slot>)"
+				)
+			})
+	}
+}
+
+impl TryFrom<ButtonActionSerializer> for ButtonAction {
+	type Error = FormatError;
+
+	fn try_from(value: ButtonActionSerializer) -> Result<Self, Self::Error> {
+		match value {
+			ButtonActionSerializer::Builtin(button) => Ok(Self::Builtin(button)),
+			ButtonActionSerializer::Other(value) => match parse_prefixed_action(&value) {
+				Some(action) => action.map_err(FormatError),
+				None => Err(FormatError(format!(
+					"could not parse `{value}` as a button action (expected an action such as \
+					 `LeftClick` or `MediaNext`, key:<key> such as `key:ctrl+c`, or macro:<slot>)"
+				))),
+			},
+		}
+	}
+}
+
+impl From<ButtonAction> for ButtonActionSerializer {
+	fn from(action: ButtonAction) -> Self {
+		match action {
+			ButtonAction::Builtin(button) => ButtonActionSerializer::Builtin(button),
+			ButtonAction::Key(combo) => ButtonActionSerializer::Other(format!("key:{combo}")),
+			ButtonAction::Macro(slot) => ButtonActionSerializer::Other(format!("macro:{slot}")),
+		}
+	}
+}
+
+impl fmt::Display for ButtonAction {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Builtin(button) => match clap::ArgEnum::to_possible_value(button) {
+				Some(value) => write!(f, "{}", value.get_name()),
+				None => write!(f, "{button:?}"),
+			},
+			Self::Key(combo) => write!(f, "key:{combo}"),
+			Self::Macro(slot) => write!(f, "macro:{slot}"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn parses_key_combos() {
+		let parse = |value| ButtonAction::try_from(value).map(ButtonAction::code);
+
+		assert_eq!(parse("key:ctrl+c"), Ok([0x21, 0x01, 0x06, 0x00]));
+		assert_eq!(parse("key:Ctrl+Shift+T"), Ok([0x21, 0x03, 0x17, 0x00]));
+		assert_eq!(parse("key:f13"), Ok([0x21, 0x00, 0x68, 0x00]));
+		assert_eq!(parse("key:ralt"), Ok([0x21, 0x40, 0x00, 0x00]));
+		assert_eq!(parse("key:super+0x65"), Ok([0x21, 0x08, 0x65, 0x00]));
+		assert_eq!(parse("media-next"), Ok([0x22, 0x01, 0x00, 0x00]));
+		assert_eq!(parse("macro:3"), Ok([0x70, 0x03, 0x00, 0x00]));
+
+		assert!(parse("key:").is_err());
+		assert!(parse("key:hyper+c").is_err());
+		assert!(parse("key:c+ctrl").is_err());
+		assert!(parse("key:f25").is_err());
+		assert!(parse("jump").is_err());
+	}
+
+	#[test]
+	fn round_trips_actions() {
+		let actions = [
+			"key:ctrl+c",
+			"key:shift+alt+f5",
+			"key:rsuper",
+			"key:pagedown",
+			"macro:2",
+			"dpi-lock-900",
+		];
+
+		for action in actions {
+			let parsed = ButtonAction::try_from(action).unwrap();
+			assert_eq!(parsed.to_string(), action);
+			assert_eq!(ButtonAction::from_code(parsed.code()), Some(parsed));
+
+			let json = serde_json::to_value(parsed).unwrap();
+			assert_eq!(serde_json::from_value::<ButtonAction>(json).unwrap(), parsed);
+		}
+
+		assert_eq!(
+			serde_json::to_value(ButtonAction::try_from("key:ctrl+c").unwrap()).unwrap(),
+			json!("key:ctrl+c")
+		);
+		assert_eq!(
+			serde_json::from_value::<ButtonAction>(json!("MediaNext")).unwrap(),
+			ButtonAction::Builtin(MouseButtonType::MediaNext)
+		);
+		assert_eq!(ButtonAction::from_code([0x21, 0x00, 0x00, 0x00]), None);
+	}
+}
@@ -0,0 +1,394 @@
+use std::{
+	fmt::{self, Formatter},
+	fs,
+	path::Path,
+	str::FromStr,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{buttons, validate::Problem, FormatError};
+use crate::error::Error;
+
+/// Number of macros which can be stored on the mouse.
+pub const MACRO_SLOTS: u8 = 8;
+/// Most key and button events a single macro can contain.
+pub const MACRO_EVENTS: usize = 128;
+
+/// Mouse buttons which can be pressed by a macro, by their bit in the
+/// `Type 3` packet.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum MacroButton {
+	Left = 0x01,
+	Right = 0x02,
+	Middle = 0x04,
+	Back = 0x08,
+	Forward = 0x10,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct MacroKeySerializer(String);
+
+/// Keyboard key pressed by a macro, stored as its HID usage id. Keys are
+/// named like in [`buttons::KeyCombo`], and modifiers (`ctrl`, `shift`,
+/// ...) are keys of their own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "MacroKeySerializer")]
+#[serde(into = "MacroKeySerializer")]
+pub struct MacroKey(pub u8);
+
+/// HID usage id of the first modifier key (left ctrl). The others follow in
+/// the order of [`buttons::MODIFIERS`].
+const FIRST_MODIFIER_KEY: u8 = 0xe0;
+
+impl TryFrom<&str> for MacroKey {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		buttons::MODIFIERS
+			.iter()
+			.position(|modifier| modifier.eq_ignore_ascii_case(value))
+			.map(|bit| FIRST_MODIFIER_KEY + bit as u8)
+			.or_else(|| buttons::key_usage(value))
+			.map(Self)
+			.ok_or_else(|| format!("unknown key `{value}`"))
+	}
+}
+
+impl fmt::Display for MacroKey {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self.0.checked_sub(FIRST_MODIFIER_KEY) {
+			Some(bit) if (bit as usize) < buttons::MODIFIERS.len() =>
+				write!(f, "{}", buttons::MODIFIERS[bit as usize]),
+			_ => write!(f, "{}", buttons::key_name(self.0)),
+		}
+	}
+}
+
+impl TryFrom<MacroKeySerializer> for MacroKey {
+	type Error = FormatError;
+
+	fn try_from(value: MacroKeySerializer) -> Result<Self, Self::Error> {
+		TryFrom::try_from(&value.0 as &str).map_err(FormatError)
+	}
+}
+
+impl From<MacroKey> for MacroKeySerializer {
+	fn from(key: MacroKey) -> Self {
+		MacroKeySerializer(key.to_string())
+	}
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroEvent {
+	KeyDown(MacroKey),
+	KeyUp(MacroKey),
+	ButtonDown(MacroButton),
+	ButtonUp(MacroButton),
+	/// Wait before the next event (milliseconds)
+	Delay(u16),
+}
+
+/// A key or button event as stored on the mouse, where delays are part of
+/// the preceding event rather than events of their own (see
+/// [`Macro::steps`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroStep {
+	KeyDown(MacroKey),
+	KeyUp(MacroKey),
+	ButtonDown(MacroButton),
+	ButtonUp(MacroButton),
+}
+
+impl From<MacroStep> for MacroEvent {
+	fn from(step: MacroStep) -> Self {
+		match step {
+			MacroStep::KeyDown(key) => Self::KeyDown(key),
+			MacroStep::KeyUp(key) => Self::KeyUp(key),
+			MacroStep::ButtonDown(button) => Self::ButtonDown(button),
+			MacroStep::ButtonUp(button) => Self::ButtonUp(button),
+		}
+	}
+}
+
+/// How a macro is played when its button is pressed.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroRepeat {
+	/// Play the macro this many times
+	Times(u8),
+	/// Repeat the macro until the button is released
+	WhileHeld,
+	/// Start repeating the macro on one press, and stop on the next
+	Toggle,
+}
+
+impl Default for MacroRepeat {
+	fn default() -> Self {
+		Self::Times(1)
+	}
+}
+
+/// A sequence of key and mouse button events with delays, stored on the
+/// mouse in one of [`MACRO_SLOTS`] slots and played by a
+/// [`buttons::ButtonAction::Macro`] button.
+///
+/// Macros are written to files either as JSON or as text with one event
+/// per line (see [`Macro::parse`]).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Macro {
+	pub repeat: MacroRepeat,
+	pub events: Vec<MacroEvent>,
+}
+
+impl Macro {
+	/// Parses the text macro format: one event per line, written as
+	/// `key-down <key>`, `key-up <key>`, `button-down <button>`,
+	/// `button-up <button>` or `delay <milliseconds>`, and optionally a
+	/// `repeat <times>`, `repeat while-held` or `repeat toggle` line.
+	/// Everything after a `#` is a comment.
+	///
+	/// # Errors
+	///
+	/// Every line which cannot be parsed, with paths of the form `line <n>`.
+	pub fn parse(text: &str) -> Result<Self, Vec<Problem>> {
+		let mut parsed = Self::default();
+		let mut problems = Vec::new();
+
+		for (i, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue
+			}
+
+			let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+			let argument = argument.trim();
+			let button = || {
+				<MacroButton as clap::ArgEnum>::from_str(argument, true)
+					.map_err(|_| format!("unknown mouse button `{argument}`"))
+			};
+			let event = match command {
+				"key-down" => MacroKey::try_from(argument).map(MacroEvent::KeyDown),
+				"key-up" => MacroKey::try_from(argument).map(MacroEvent::KeyUp),
+				"button-down" => button().map(MacroEvent::ButtonDown),
+				"button-up" => button().map(MacroEvent::ButtonUp),
+				"delay" => u16::from_str(argument)
+					.map(MacroEvent::Delay)
+					.map_err(|_| format!("`{argument}` is not a delay in milliseconds")),
+				"repeat" => {
+					match argument {
+						"while-held" => parsed.repeat = MacroRepeat::WhileHeld,
+						"toggle" => parsed.repeat = MacroRepeat::Toggle,
+						times => match u8::from_str(times) {
+							Ok(times) => parsed.repeat = MacroRepeat::Times(times),
+							Err(_) => problems.push(Problem::new(
+								format!("line {}", i + 1),
+								format!(
+									"`{times}` is not a repeat mode (expected a number, \
+									 `while-held` or `toggle`)"
+								),
+							)),
+						},
+					}
+					continue
+				}
+				_ => Err(format!("unknown event `{command}`")),
+			};
+
+			match event {
+				Ok(event) => parsed.events.push(event),
+				Err(e) => problems.push(Problem::new(format!("line {}", i + 1), e)),
+			}
+		}
+
+		match problems.is_empty() {
+			true => Ok(parsed),
+			false => Err(problems),
+		}
+	}
+
+	/// Loads a macro file, which is read as JSON if its name ends with
+	/// `.json`, and in the text format (see [`Macro::parse`]) otherwise.
+	///
+	/// # Errors
+	///
+	/// If the file cannot be read or parsed.
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		let text = fs::read_to_string(path)
+			.map_err(|e| Error::io(format!("read macro file {}", path.display()), e))?;
+
+		match path.extension().is_some_and(|ext| ext == "json") {
+			true => serde_json::from_str(&text).map_err(|source| Error::ConfigParse {
+				path: path.to_owned(),
+				source,
+			}),
+			false => Self::parse(&text).map_err(|problems| Error::InvalidConfig {
+				path: path.to_owned(),
+				problems,
+			}),
+		}
+	}
+
+	/// Key and button events, each with the total delay which follows it
+	/// (as stored on the mouse). Delays before the first event are
+	/// dropped.
+	pub fn steps(&self) -> Vec<(MacroStep, u32)> {
+		let mut steps = Vec::<(MacroStep, u32)>::new();
+		for event in &self.events {
+			let step = match *event {
+				MacroEvent::KeyDown(key) => MacroStep::KeyDown(key),
+				MacroEvent::KeyUp(key) => MacroStep::KeyUp(key),
+				MacroEvent::ButtonDown(button) => MacroStep::ButtonDown(button),
+				MacroEvent::ButtonUp(button) => MacroStep::ButtonUp(button),
+				MacroEvent::Delay(delay) => {
+					if let Some((_, total)) = steps.last_mut() {
+						*total += delay as u32;
+					}
+					continue
+				}
+			};
+			steps.push((step, 0));
+		}
+		steps
+	}
+
+	/// Finds every reason the macro cannot be stored on the mouse, with
+	/// paths starting at `path`.
+	pub fn validate(&self, path: &str) -> Vec<Problem> {
+		let mut problems = Vec::new();
+		let steps = self.steps();
+
+		if steps.is_empty() {
+			problems.push(Problem::new(
+				format!("{path}.events"),
+				"the macro has no key or button events",
+			));
+		}
+		if steps.len() > MACRO_EVENTS {
+			problems.push(Problem::new(
+				format!("{path}.events"),
+				format!("{} events do not fit on the mouse (at most {MACRO_EVENTS})", steps.len()),
+			));
+		}
+		if let Some(MacroEvent::Delay(_)) = self.events.first() {
+			problems.push(Problem::new(
+				format!("{path}.events[0]"),
+				"a macro cannot start with a delay",
+			));
+		}
+		if steps.iter().any(|(_, delay)| *delay > u16::MAX as u32) {
+			problems.push(Problem::new(
+				format!("{path}.events"),
+				format!("delays between two events may add up to at most {} ms", u16::MAX),
+			));
+		}
+		if let MacroRepeat::Times(0) = self.repeat {
+			problems.push(Problem::new(
+				format!("{path}.repeat"),
+				"a macro must be played at least once",
+			));
+		}
+
+		problems
+	}
+}
+
+impl fmt::Display for Macro {
+	/// Writes the macro in the text format (see [`Macro::parse`]).
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self.repeat {
+			MacroRepeat::Times(1) => {}
+			MacroRepeat::Times(times) => writeln!(f, "repeat {times}")?,
+			MacroRepeat::WhileHeld => writeln!(f, "repeat while-held")?,
+			MacroRepeat::Toggle => writeln!(f, "repeat toggle")?,
+		}
+
+		let button_name = |button: &MacroButton| {
+			clap::ArgEnum::to_possible_value(button)
+				.map(|value| value.get_name())
+				.unwrap_or_default()
+		};
+		for event in &self.events {
+			match event {
+				MacroEvent::KeyDown(key) => writeln!(f, "key-down {key}")?,
+				MacroEvent::KeyUp(key) => writeln!(f, "key-up {key}")?,
+				MacroEvent::ButtonDown(button) =>
+					writeln!(f, "button-down {}", button_name(button))?,
+				MacroEvent::ButtonUp(button) => writeln!(f, "button-up {}", button_name(button))?,
+				MacroEvent::Delay(delay) => writeln!(f, "delay {delay}")?,
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn parses_text_macros() {
+		let text = [
+			"# copy, then click",
+			"repeat 3",
+			"key-down ctrl",
+			"key-down c",
+			"delay 20",
+			"delay 5 # keys need time",
+			"key-up c",
+			"key-up ctrl",
+			"button-down left",
+			"button-up left",
+		]
+		.join("\n");
+
+		let parsed = Macro::parse(&text).unwrap();
+
+		assert_eq!(parsed.repeat, MacroRepeat::Times(3));
+		assert_eq!(parsed.steps()[1], (MacroStep::KeyDown(MacroKey(0x06)), 25));
+		assert_eq!(parsed.steps()[0], (MacroStep::KeyDown(MacroKey(0xe0)), 0));
+		assert_eq!(Macro::parse(&parsed.to_string()).unwrap(), parsed);
+		assert_eq!(
+			serde_json::to_value(&parsed.events[..3]).unwrap(),
+			json!([{ "KeyDown": "ctrl" }, { "KeyDown": "c" }, { "Delay": 20 }])
+		);
+		assert!(parsed.validate("macros.0").is_empty());
+
+		let problems = Macro::parse("key-down ctrl\nkey-press c\nbutton-up side\ndelay -1\n")
+			.unwrap_err()
+			.into_iter()
+			.map(|problem| problem.path)
+			.collect::<Vec<_>>();
+		assert_eq!(problems, ["line 2", "line 3", "line 4"]);
+	}
+
+	#[test]
+	fn validates_macros() {
+		let mut invalid = Macro {
+			repeat: MacroRepeat::Times(0),
+			events: vec![MacroEvent::Delay(10), MacroEvent::Delay(20)],
+		};
+
+		assert_eq!(
+			invalid
+				.validate("macros.1")
+				.into_iter()
+				.map(|problem| problem.path)
+				.collect::<Vec<_>>(),
+			["macros.1.events", "macros.1.events[0]", "macros.1.repeat"]
+		);
+
+		invalid.events = vec![
+			MacroEvent::ButtonDown(MacroButton::Left),
+			MacroEvent::Delay(u16::MAX),
+			MacroEvent::Delay(1),
+		];
+		invalid.repeat = MacroRepeat::Toggle;
+		assert_eq!(invalid.validate("m").len(), 1);
+	}
+}
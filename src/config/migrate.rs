@@ -0,0 +1,150 @@
+use serde_json::{Map, Value};
+
+use super::json_diff;
+
+/// Version of the config format written by this build. Bump it (and add a
+/// step to [`MIGRATIONS`]) whenever a field is renamed or changes meaning.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// Upgrades a config object by one version.
+type Step = fn(&mut Map<String, Value>);
+
+/// `MIGRATIONS[i]` upgrades a version `i` config to version `i + 1`.
+/// Version 0 is any config written before configs were versioned.
+static MIGRATIONS: [Step; CURRENT_VERSION as usize] = [
+	// version 1 only adds the version field
+	|_| {},
+	// version 2 stores DPI in DPI instead of hundreds of DPI
+	|config| {
+		let Some(Value::Array(dpis)) = config.get_mut("dpi") else {
+			return
+		};
+		for dpi in dpis.iter_mut().filter_map(Value::as_object_mut) {
+			for key in ["x_dpi", "y_dpi"] {
+				if let Some(hundreds) = dpi.get(key).and_then(Value::as_u64) {
+					dpi.insert(key.to_owned(), (hundreds * 100).into());
+				}
+			}
+		}
+	},
+];
+
+/// The config is newer than [`CURRENT_VERSION`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnsupportedVersion(pub u64);
+
+/// What [`migrate`] changed.
+#[derive(Debug)]
+pub struct Migration {
+	/// Version of the config before migrating
+	pub from: u32,
+	/// Every changed value as `(<dotted path>, <old value>, <new value>)`
+	pub changes: Vec<(String, Value, Value)>,
+}
+
+impl Migration {
+	pub fn is_needed(&self) -> bool {
+		self.from < CURRENT_VERSION
+	}
+}
+
+/// Upgrades the config in `json` to [`CURRENT_VERSION`].
+///
+/// Values which are not objects, or have a version which is not an integer,
+/// are left alone for deserialization to reject.
+///
+/// # Errors
+///
+/// If the config is newer than [`CURRENT_VERSION`].
+pub fn migrate(json: &mut Value) -> Result<Migration, UnsupportedVersion> {
+	let unchanged = Migration {
+		from: CURRENT_VERSION,
+		changes: Vec::new(),
+	};
+
+	let Value::Object(config) = json else {
+		return Ok(unchanged)
+	};
+	let from = match config.get("version") {
+		None => 0,
+		Some(Value::Number(version)) => match version.as_u64() {
+			Some(version) if version > CURRENT_VERSION as u64 =>
+				return Err(UnsupportedVersion(version)),
+			Some(version) => version as u32,
+			None => return Ok(unchanged),
+		},
+		Some(_) => return Ok(unchanged),
+	};
+
+	let old = Value::Object(config.clone());
+	for step in &MIGRATIONS[from as usize..] {
+		step(config);
+	}
+	config.insert("version".to_owned(), CURRENT_VERSION.into());
+
+	Ok(Migration {
+		from,
+		changes: json_diff(&old, json),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+	use crate::config::Config;
+
+	#[test]
+	fn upgrades_unversioned_configs() {
+		let mut json = json!({ "current_dpi": 2 });
+
+		let migration = migrate(&mut json).unwrap();
+
+		assert_eq!(migration.from, 0);
+		assert!(migration.is_needed());
+		assert_eq!(migration.changes, [(
+			"version".to_owned(),
+			Value::Null,
+			json!(CURRENT_VERSION)
+		)]);
+		assert_eq!(json, json!({ "current_dpi": 2, "version": CURRENT_VERSION }));
+	}
+
+	#[test]
+	fn converts_dpi_from_hundreds() {
+		let mut json = json!({ "version": 1, "dpi": [{ "x_dpi": 4, "y_dpi": 16 }] });
+
+		let migration = migrate(&mut json).unwrap();
+
+		assert_eq!(migration.from, 1);
+		assert_eq!(json["dpi"], json!([{ "x_dpi": 400, "y_dpi": 1600 }]));
+		assert_eq!(
+			migration
+				.changes
+				.iter()
+				.map(|(path, ..)| path.as_str())
+				.collect::<Vec<_>>(),
+			["dpi.0.x_dpi", "dpi.0.y_dpi", "version"]
+		);
+	}
+
+	#[test]
+	fn leaves_current_configs_alone() {
+		let mut json = serde_json::to_value(Config::default()).unwrap();
+		let before = json.clone();
+
+		let migration = migrate(&mut json).unwrap();
+
+		assert!(!migration.is_needed());
+		assert!(migration.changes.is_empty());
+		assert_eq!(json, before);
+	}
+
+	#[test]
+	fn rejects_newer_configs() {
+		let mut json = json!({ "version": CURRENT_VERSION + 1 });
+
+		assert_eq!(migrate(&mut json).unwrap_err(), UnsupportedVersion(CURRENT_VERSION as u64 + 1));
+	}
+}
@@ -0,0 +1,198 @@
+use schemars::{
+	gen::SchemaGenerator,
+	schema::{
+		InstanceType,
+		Metadata,
+		NumberValidation,
+		RootSchema,
+		Schema,
+		SchemaObject,
+		StringValidation,
+		SubschemaValidation,
+	},
+	JsonSchema,
+};
+
+use super::{
+	buttons::{ButtonAction, KeyCombo},
+	macros::MacroKey,
+	Color,
+	Config,
+	HexBytes,
+	MouseButtonType,
+	RangedByte,
+};
+use crate::usb::PACKET_DPI;
+
+/// Schema for a string matching `pattern`.
+fn string_schema(pattern: String, description: String) -> Schema {
+	SchemaObject {
+		instance_type: Some(InstanceType::String.into()),
+		string: Some(Box::new(StringValidation {
+			pattern: Some(pattern),
+			..Default::default()
+		})),
+		metadata: Some(Box::new(Metadata {
+			description: Some(description),
+			..Default::default()
+		})),
+		..Default::default()
+	}
+	.into()
+}
+
+/// Schema for a DPI value: every value which fits in a packet (see
+/// [`PACKET_DPI`]).
+pub(super) fn dpi_schema(_: &mut SchemaGenerator) -> Schema {
+	SchemaObject {
+		instance_type: Some(InstanceType::Integer.into()),
+		format: Some("uint16".to_owned()),
+		number: Some(Box::new(NumberValidation {
+			multiple_of: Some(PACKET_DPI.step.into()),
+			minimum: Some(PACKET_DPI.min.into()),
+			maximum: Some(PACKET_DPI.max.into()),
+			..Default::default()
+		})),
+		..Default::default()
+	}
+	.into()
+}
+
+impl JsonSchema for Color {
+	fn schema_name() -> String {
+		"Color".to_owned()
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		string_schema(
+			"^[0-9a-fA-F]{6}$".to_owned(),
+			"Hex color without a leading `#` (e.g. `ff0000`)".to_owned(),
+		)
+	}
+}
+
+impl<const MIN: u8, const MAX: u8> JsonSchema for RangedByte<MIN, MAX> {
+	fn is_referenceable() -> bool {
+		false
+	}
+
+	fn schema_name() -> String {
+		format!("RangedByte_{MIN}_{MAX}")
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		SchemaObject {
+			instance_type: Some(InstanceType::Integer.into()),
+			format: Some("uint8".to_owned()),
+			number: Some(Box::new(NumberValidation {
+				minimum: Some(MIN.into()),
+				maximum: Some(MAX.into()),
+				..Default::default()
+			})),
+			..Default::default()
+		}
+		.into()
+	}
+}
+
+impl<const N: usize> JsonSchema for HexBytes<N> {
+	fn is_referenceable() -> bool {
+		false
+	}
+
+	fn schema_name() -> String {
+		format!("HexBytes_{N}")
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		string_schema(
+			format!("^\\s*([0-9a-fA-F]{{2}}\\s*){{{N}}}$"),
+			format!("{N} hex bytes, optionally separated by whitespace"),
+		)
+	}
+}
+
+impl JsonSchema for ButtonAction {
+	fn schema_name() -> String {
+		"ButtonAction".to_owned()
+	}
+
+	fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+		SchemaObject {
+			subschemas: Some(Box::new(SubschemaValidation {
+				any_of: Some(vec![
+					gen.subschema_for::<MouseButtonType>(),
+					string_schema(
+						"^key:.+$".to_owned(),
+						"Keyboard key with optional modifiers (e.g. `key:ctrl+c`)".to_owned(),
+					),
+					string_schema(
+						"^macro:[0-9]+$".to_owned(),
+						"Macro stored in a slot (e.g. `macro:0`)".to_owned(),
+					),
+				]),
+				..Default::default()
+			})),
+			..Default::default()
+		}
+		.into()
+	}
+}
+
+impl JsonSchema for MacroKey {
+	fn schema_name() -> String {
+		"MacroKey".to_owned()
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		string_schema(
+			"^.+$".to_owned(),
+			"Key name (e.g. `c`, `ctrl`, `f5` or `enter`) or HID usage id (e.g. `0x65`)".to_owned(),
+		)
+	}
+}
+
+impl JsonSchema for KeyCombo {
+	fn schema_name() -> String {
+		"KeyCombo".to_owned()
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		string_schema(
+			"^.+$".to_owned(),
+			"Keys joined by `+`, modifiers first (e.g. `ctrl+c` or `super+2`)".to_owned(),
+		)
+	}
+}
+
+impl Config {
+	/// JSON Schema describing config files, for editors to autocomplete and
+	/// validate them with.
+	pub fn schema() -> RootSchema {
+		schemars::schema_for!(Config)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn describes_constraints() {
+		let schema = serde_json::to_value(Config::schema()).unwrap();
+		let definitions = &schema["definitions"];
+
+		assert_eq!(definitions["Tail"]["properties"]["speed"]["minimum"], json!(1.0));
+		assert_eq!(definitions["Tail"]["properties"]["speed"]["maximum"], json!(3.0));
+		assert_eq!(definitions["Color"]["pattern"], json!("^[0-9a-fA-F]{6}$"));
+		assert_eq!(definitions["Dpi"]["properties"]["x_dpi"]["multipleOf"], json!(100.0));
+		assert_eq!(definitions["Dpi"]["properties"]["y_dpi"]["maximum"], json!(25500.0));
+		assert_eq!(
+			definitions["PollingRate"]["enum"],
+			json!(["125hz", "250hz", "500hz", "1000hz"])
+		);
+		assert_eq!(schema["properties"]["version"]["default"], json!(2));
+	}
+}
@@ -0,0 +1,175 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+	buttons::{ButtonAction, KeyCombo, MODIFIERS},
+	validate::Problem,
+	Config,
+	MouseButtonType,
+};
+
+/// On-device actions which software buttons are bound to, with the Linux
+/// key code (`KEY_*` in `linux/input-event-codes.h`) the kernel reports when
+/// the mouse sends them. They are launcher keys, which are rarely used
+/// otherwise.
+pub const CARRIERS: [(MouseButtonType, u16); 5] = [
+	(MouseButtonType::Calculator, 140),  // KEY_CALC
+	(MouseButtonType::Email, 155),       // KEY_MAIL
+	(MouseButtonType::Explorer, 144),    // KEY_FILE
+	(MouseButtonType::HomePage, 172),    // KEY_HOMEPAGE
+	(MouseButtonType::MediaPlayer, 171), // KEY_CONFIG
+];
+
+/// Linux key codes of the keys with HID usage ids `0x00` to `0x73`, `0` if
+/// there is none. Taken from the kernel's `hid_keyboard` table.
+#[rustfmt::skip]
+const LINUX_KEY_CODES: [u8; 0x74] = [
+	  0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+	 50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+	  4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+	 27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+	 65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+	105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+	 72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+	191, 192, 193, 194,
+];
+
+/// Linux key codes of the modifiers, in the order of [`MODIFIERS`].
+const LINUX_MODIFIER_CODES: [u16; MODIFIERS.len()] = [29, 42, 56, 125, 97, 54, 100, 126];
+
+/// Action run by `glorious-mouse-control daemon` when a software button is
+/// pressed, for actions the mouse cannot store itself.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+pub enum SoftwareAction {
+	/// Run a shell command (with `sh -c`)
+	Command(String),
+	/// Press keys on a virtual keyboard for as long as the button is held
+	Key(KeyCombo),
+}
+
+impl SoftwareAction {
+	/// Finds every reason the action cannot be run, with paths starting at
+	/// `path`.
+	pub fn validate(&self, path: &str) -> Vec<Problem> {
+		match self {
+			Self::Command(command) if command.trim().is_empty() =>
+				vec![Problem::new(path, "the command is empty")],
+			Self::Key(combo) if linux_key_codes(combo).is_none() => vec![Problem::new(
+				path,
+				format!("`{combo}` cannot be pressed on a virtual keyboard"),
+			)],
+			_ => Vec::new(),
+		}
+	}
+}
+
+/// Linux key codes to press for `combo`, modifiers first, or [`None`] if
+/// its key has no Linux key code.
+pub fn linux_key_codes(combo: &KeyCombo) -> Option<Vec<u16>> {
+	let mut codes = LINUX_MODIFIER_CODES
+		.iter()
+		.enumerate()
+		.filter(|(bit, _)| combo.modifiers & (1 << bit) != 0)
+		.map(|(_, code)| *code)
+		.collect::<Vec<_>>();
+
+	if combo.key != 0 {
+		match LINUX_KEY_CODES.get(combo.key as usize) {
+			Some(code) if *code != 0 => codes.push(*code as u16),
+			_ => return None,
+		}
+	}
+
+	Some(codes)
+}
+
+/// A software button and the carrier it is bound to on the mouse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftwareBinding<'c> {
+	/// Button name, as in [`Config::buttons`]
+	pub button: &'c str,
+	pub action: &'c SoftwareAction,
+	/// On-device action the button sends (see [`CARRIERS`])
+	pub carrier: MouseButtonType,
+	/// Linux key code the kernel reports for `carrier`
+	pub key_code: u16,
+}
+
+impl Config {
+	/// Binds every software button to a carrier, in the order of
+	/// [`CARRIERS`] and skipping carriers which [`Config::buttons`] already
+	/// use. Buttons left without a carrier are reported by
+	/// [`Config::validate`].
+	pub fn software_bindings(&self) -> Vec<SoftwareBinding<'_>> {
+		let carriers = CARRIERS.iter().filter(|(carrier, _)| {
+			!self
+				.buttons
+				.0
+				.values()
+				.any(|action| *action == ButtonAction::Builtin(*carrier))
+		});
+
+		self.software_buttons
+			.iter()
+			.zip(carriers)
+			.map(|((button, action), (carrier, key_code))| SoftwareBinding {
+				button,
+				action,
+				carrier: *carrier,
+				key_code: *key_code,
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn binds_free_carriers() {
+		let mut config = Config::default();
+		config
+			.buttons
+			.0
+			.insert("dpi".to_owned(), MouseButtonType::Calculator.into());
+		for button in ["back", "forward"] {
+			config
+				.software_buttons
+				.insert(button.to_owned(), SoftwareAction::Command("true".to_owned()));
+		}
+
+		assert_eq!(
+			config
+				.software_bindings()
+				.iter()
+				.map(|binding| (binding.button, binding.carrier, binding.key_code))
+				.collect::<Vec<_>>(),
+			[
+				("back", MouseButtonType::Email, 155),
+				("forward", MouseButtonType::Explorer, 144)
+			]
+		);
+	}
+
+	#[test]
+	fn parses_software_actions() {
+		let json = r#"{"back": {"Command": "notify-send hello"}, "forward": {"Key": "super+2"}}"#;
+		let actions: std::collections::BTreeMap<String, SoftwareAction> =
+			serde_json::from_str(json).unwrap();
+
+		assert_eq!(actions["back"], SoftwareAction::Command("notify-send hello".to_owned()));
+		assert_eq!(actions["forward"], SoftwareAction::Key(KeyCombo::try_from("super+2").unwrap()));
+		assert_eq!(serde_json::to_string(&actions["forward"]).unwrap(), r#"{"Key":"super+2"}"#);
+	}
+
+	#[test]
+	fn finds_linux_key_codes() {
+		let codes = |combo: &str| linux_key_codes(&KeyCombo::try_from(combo).unwrap());
+
+		assert_eq!(codes("super+2"), Some(vec![125, 3]));
+		assert_eq!(codes("ctrl+alt+t"), Some(vec![29, 56, 20]));
+		assert_eq!(codes("f13"), Some(vec![183]));
+		assert_eq!(codes("0x90"), None);
+	}
+}
@@ -0,0 +1,282 @@
+use std::fmt::{self, Formatter};
+
+use serde_json::Value;
+
+use super::{
+	buttons::ButtonAction,
+	lighting,
+	macros::MACRO_SLOTS,
+	software::CARRIERS,
+	Config,
+	Dpi,
+	RangedByte,
+};
+use crate::usb::PACKET_DPI;
+
+/// A mistake in a config, at the JSON path `path` (e.g.
+/// `lighting.breathing.colors[3]` or `dpi[2].x_dpi`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+	pub path: String,
+	pub message: String,
+}
+
+impl Problem {
+	pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+		Self {
+			path: path.into(),
+			message: message.into(),
+		}
+	}
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self.path.is_empty() {
+			true => write!(f, "{}", self.message),
+			false => write!(f, "{}: {}", self.path, self.message),
+		}
+	}
+}
+
+impl<const MIN: u8, const MAX: u8> RangedByte<MIN, MAX> {
+	fn validate(&self, path: &str, problems: &mut Vec<Problem>) {
+		if !(MIN..=MAX).contains(&self.0) {
+			problems.push(Problem::new(path, format!("{} was not in range {MIN}..{MAX}", self.0)));
+		}
+	}
+}
+
+impl Config {
+	/// Finds every value which cannot be applied to a mouse. DPI values are
+	/// only checked against what fits in a packet, see
+	/// [`crate::usb::Model::validate`] for the limits of each model.
+	pub fn validate(&self) -> Vec<Problem> {
+		let mut problems = Vec::new();
+		let lighting::Lighting {
+			solid,
+			rainbow,
+			breathing,
+			tail,
+			fade,
+			rave,
+			wave,
+			breathing_single,
+			..
+		} = &self.lighting;
+
+		for (path, byte) in [
+			("lighting.solid.brightness", &solid.brightness),
+			("lighting.breathing.brightness", &breathing.brightness),
+			("lighting.tail.brightness", &tail.brightness),
+			("lighting.rave.brightness", &rave.brightness),
+			("lighting.wave.brightness", &wave.brightness),
+		] {
+			byte.validate(path, &mut problems);
+		}
+		for (path, byte) in [
+			("lighting.rainbow.speed", &rainbow.speed),
+			("lighting.breathing.speed", &breathing.speed),
+			("lighting.tail.speed", &tail.speed),
+			("lighting.fade.speed", &fade.speed),
+			("lighting.rave.speed", &rave.speed),
+			("lighting.wave.speed", &wave.speed),
+			("lighting.breathing_single.speed", &breathing_single.speed),
+		] {
+			byte.validate(path, &mut problems);
+		}
+		self.current_dpi.validate("current_dpi", &mut problems);
+
+		for (i, Dpi { x_dpi, y_dpi, .. }) in self.dpi.iter().enumerate() {
+			for (axis, dpi) in [("x_dpi", x_dpi), ("y_dpi", y_dpi)] {
+				if let Err(e) = PACKET_DPI.check(*dpi, "the protocol") {
+					problems.push(Problem::new(format!("dpi[{i}].{axis}"), e));
+				}
+			}
+		}
+
+		for (slot, macro_) in &self.macros {
+			match *slot < MACRO_SLOTS {
+				true => problems.extend(macro_.validate(&format!("macros.{slot}"))),
+				false => problems.push(Problem::new(
+					format!("macros.{slot}"),
+					format!("the mouse only has {MACRO_SLOTS} macro slots"),
+				)),
+			}
+		}
+		let bound = self.software_bindings().len();
+		for (i, (name, action)) in self.software_buttons.iter().enumerate() {
+			let path = format!("software_buttons.{name}");
+			problems.extend(action.validate(&path));
+			if i >= bound {
+				problems.push(Problem::new(
+					path,
+					format!(
+						"no on-device action is left to bind the button to (software buttons use \
+						 {}, unless other buttons do)",
+						CARRIERS
+							.iter()
+							.map(|(carrier, _)| format!("{carrier:?}"))
+							.collect::<Vec<_>>()
+							.join(", ")
+					),
+				));
+			}
+		}
+		for (name, action) in &self.buttons.0 {
+			if let ButtonAction::Macro(slot) = action {
+				if !self.macros.contains_key(slot) {
+					problems.push(Problem::new(
+						format!("buttons.{name}"),
+						format!("macro {slot} is not defined"),
+					));
+				}
+			}
+		}
+
+		if !self.dpi.iter().any(|Dpi { enable, .. }| *enable) {
+			problems.push(Problem::new("dpi", "at least one DPI must be enabled"));
+		} else if let Some(Dpi { enable: false, .. }) = self.dpi.get(*self.current_dpi as usize) {
+			problems.push(Problem::new(
+				"current_dpi",
+				format!("DPI {} is selected but not enabled", *self.current_dpi),
+			));
+		}
+
+		problems
+	}
+
+	/// Deserializes a config, collecting every value which does not match
+	/// [`Config`] instead of stopping at the first one. Such values are
+	/// replaced with their defaults (or the whole config, if they cannot be
+	/// replaced). [`Config::validate`] is not run.
+	pub fn from_json(mut json: Value) -> (Self, Vec<Problem>) {
+		// only serialization of out of range bytes can fail, and the defaults
+		// are in range
+		let defaults = serde_json::to_value(Config::default()).unwrap();
+		let mut problems = Vec::new();
+
+		loop {
+			let error = match serde_path_to_error::deserialize::<_, Config>(&json) {
+				Ok(config) => return (config, problems),
+				Err(error) => error,
+			};
+
+			let path = error.path().to_string();
+			problems.push(Problem::new(
+				match path.as_str() {
+					"." => "",
+					path => path,
+				},
+				error.inner().to_string(),
+			));
+
+			let pointer = json_pointer(error.path());
+			let default = defaults.pointer(&pointer);
+			match (json.pointer_mut(&pointer), default) {
+				(Some(value), Some(default)) if value != default => *value = default.clone(),
+				// the value cannot be replaced, so the same error would be found again
+				_ => return (Config::default(), problems),
+			}
+		}
+	}
+}
+
+/// Converts a path found by `serde_path_to_error` to a JSON pointer.
+fn json_pointer(path: &serde_path_to_error::Path) -> String {
+	use serde_path_to_error::Segment;
+
+	path.iter()
+		.map(|segment| match segment {
+			Segment::Seq { index } => format!("/{index}"),
+			Segment::Map { key } => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+			Segment::Enum { variant } => format!("/{variant}"),
+			Segment::Unknown => "/?".to_owned(),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	#[test]
+	fn collects_every_problem() {
+		let mut json = serde_json::to_value(Config::default()).unwrap();
+		json["lighting"]["breathing"]["colors"][3] = json!("nope");
+		json["lighting"]["tail"]["speed"] = json!(7);
+		json["polling_rate"] = json!("9000hz");
+
+		let (config, problems) = Config::from_json(json);
+
+		assert_eq!(*config.lighting.tail.speed, 2);
+
+		assert_eq!(
+			problems
+				.iter()
+				.map(|problem| problem.path.as_str())
+				.collect::<Vec<_>>(),
+			[
+				"lighting.breathing.colors[3]",
+				"lighting.tail.speed",
+				"polling_rate"
+			]
+		);
+	}
+
+	#[test]
+	fn validates_values() {
+		let mut config = Config::default();
+		assert!(config.validate().is_empty());
+
+		config.lighting.rave.brightness = RangedByte(0);
+		config.dpi[2].x_dpi = 30000;
+		config.current_dpi = RangedByte(5);
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), ButtonAction::Macro(1));
+
+		assert_eq!(config.validate(), [
+			Problem::new("lighting.rave.brightness", "0 was not in range 1..4"),
+			Problem::new("dpi[2].x_dpi", "30 000 exceeds the protocol maximum of 25 500"),
+			Problem::new("buttons.back", "macro 1 is not defined"),
+			Problem::new("current_dpi", "DPI 5 is selected but not enabled"),
+		]);
+
+		for dpi in &mut config.dpi {
+			dpi.enable = false;
+		}
+		assert!(config
+			.validate()
+			.contains(&Problem::new("dpi", "at least one DPI must be enabled")));
+	}
+
+	#[test]
+	fn validates_software_buttons() {
+		use super::super::software::SoftwareAction;
+
+		let mut config = Config::default();
+		for slot in 6..12 {
+			let command = match slot {
+				6 => " ",
+				_ => "true",
+			};
+			config
+				.software_buttons
+				.insert(format!("slot-{slot}"), SoftwareAction::Command(command.to_owned()));
+		}
+
+		let problems = config.validate();
+		assert_eq!(
+			problems
+				.iter()
+				.map(|problem| problem.path.as_str())
+				.collect::<Vec<_>>(),
+			["software_buttons.slot-6", "software_buttons.slot-9"]
+		);
+		assert_eq!(problems[0].message, "the command is empty");
+	}
+}
@@ -0,0 +1,283 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Display,
+	sync::mpsc,
+	thread,
+	time::{Duration, Instant, SystemTime},
+};
+
+use glorious_mouse_control::{
+	config::Config,
+	error::Error,
+	signal,
+	store::{self, ConfigStore},
+	usb::{device::Device, record},
+};
+use rusb::{GlobalContext, Hotplug, UsbContext};
+
+use crate::{command::ExtraFlags, config_paths};
+
+mod remap;
+
+/// Prints a log line in `key=value` form to stderr.
+fn log(level: &str, event: &str, fields: &[(&str, &dyn Display)]) {
+	let timestamp = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|time| time.as_secs_f64())
+		.unwrap_or_default();
+
+	let mut line = format!("ts={timestamp:.3} level={level} event={event}");
+	for (key, value) in fields {
+		let value = value.to_string();
+		match value.contains(char::is_whitespace) {
+			true => line.push_str(&format!(" {key}={value:?}")),
+			false => line.push_str(&format!(" {key}={value}")),
+		}
+	}
+
+	eprintln!("{line}");
+}
+
+/// Fields describing `device` for [`log`]
+macro_rules! device_fields {
+	($device:expr $(, $($field:tt)*)?) => {
+		&[
+			("model", &$device.model.name),
+			("bus", &$device.bus()),
+			("address", &$device.address()),
+			("port", &$device.port_path()),
+			$($($field)*)?
+		]
+	};
+}
+
+enum HotplugEvent {
+	Arrived(Device),
+	Left(Device),
+}
+
+/// Forwards hotplug events for target devices to the daemon loop. USB
+/// transfers are not allowed inside hotplug callbacks, so all work happens
+/// on the receiving end.
+struct HotplugForwarder(mpsc::Sender<HotplugEvent>);
+
+impl Hotplug<GlobalContext> for HotplugForwarder {
+	fn device_arrived(&mut self, device: rusb::Device<GlobalContext>) {
+		if let Some(device) = Device::new(device) {
+			let _ = self.0.send(HotplugEvent::Arrived(device));
+		}
+	}
+
+	fn device_left(&mut self, device: rusb::Device<GlobalContext>) {
+		if let Some(device) = Device::new(device) {
+			let _ = self.0.send(HotplugEvent::Left(device));
+		}
+	}
+}
+
+/// Applies the stored config for `device`, the same way as running the
+/// program without arguments, and returns it so that its software buttons
+/// can be handled.
+///
+/// # Errors
+///
+/// If the config cannot be loaded or applied.
+fn apply_stored_config(
+	store: &ConfigStore,
+	device: &Device,
+	flags: &ExtraFlags,
+) -> Result<Config, Error> {
+	let (load_path, _) = config_paths(store, &store::ConfigTarget::device(device), flags)?;
+	let mut config = store::load_config(&load_path)?.unwrap_or_default();
+	config.check()?;
+
+	device.apply(&config, &flags.options(device))?;
+	log(
+		"info",
+		"config_applied",
+		device_fields!(device, ("config", &load_path.display())),
+	);
+
+	Ok(config)
+}
+
+/// Time between attempts to start handling the software buttons of a
+/// device, and the number of attempts. The kernel recreates the input nodes
+/// of a mouse after it is connected, so they may not exist yet once its
+/// config has been applied.
+const REMAP_RETRY: (Duration, u32) = (Duration::from_millis(500), 10);
+
+/// A device whose software buttons are not handled yet.
+struct PendingRemap {
+	device: Device,
+	config: Config,
+	attempts: u32,
+	deadline: Instant,
+}
+
+/// Starts handling the software buttons of `remap` (see [`remap::start`]).
+/// Returns `remap` again, with its next deadline, if it should be retried.
+fn start_remap(mut remap: PendingRemap) -> Option<PendingRemap> {
+	let (interval, attempts) = REMAP_RETRY;
+	let device = &remap.device;
+
+	match remap::start(device, &remap.config) {
+		Ok(()) => log(
+			"info",
+			"remap_started",
+			device_fields!(device, ("buttons", &remap.config.software_buttons.len())),
+		),
+		Err(e) if remap.attempts + 1 < attempts => {
+			log("info", "remap_retry", device_fields!(device, ("error", &e)));
+			remap.attempts += 1;
+			remap.deadline = Instant::now() + interval;
+			return Some(remap)
+		}
+		Err(e) => log("error", "remap_failed", device_fields!(device, ("error", &e))),
+	}
+	None
+}
+
+/// Shortest and longest time to wait before handling USB events again
+/// after a failure
+const USB_EVENTS_BACKOFF: (Duration, Duration) =
+	(Duration::from_millis(100), Duration::from_secs(30));
+
+/// Handles libusb events (which run the hotplug callbacks) forever. After a
+/// failure, waits before trying again, doubling the wait for each failure
+/// in a row up to [`USB_EVENTS_BACKOFF`], so a persistent failure does not
+/// spin.
+fn handle_usb_events() {
+	let (min_backoff, max_backoff) = USB_EVENTS_BACKOFF;
+	let mut backoff = min_backoff;
+
+	loop {
+		match GlobalContext::default().handle_events(None) {
+			Ok(()) => backoff = min_backoff,
+			Err(e) => {
+				log("error", "usb_events_failed", &[
+					("error", &e),
+					("retry_ms", &backoff.as_millis()),
+				]);
+				thread::sleep(backoff);
+				backoff = (backoff * 2).min(max_backoff);
+			}
+		}
+	}
+}
+
+/// Watches for target devices and applies their stored config whenever one
+/// is connected. A device must stay connected for `settle_time` before its
+/// config is applied, so flapping connections are only configured once they
+/// are stable.
+///
+/// Failing to configure a device is logged and does not stop the daemon.
+///
+/// # Errors
+///
+/// If hotplug is not supported, or the hotplug callback cannot be
+/// registered.
+pub fn run(flags: &ExtraFlags, settle_time: Duration) -> Result<(), Error> {
+	if !rusb::has_hotplug() {
+		return Err(Error::usb("use hotplug with this libusb build", rusb::Error::NotSupported))
+	}
+
+	let (sender, receiver) = mpsc::channel();
+
+	// existing devices are enumerated as arrivals
+	let _registration = rusb::HotplugBuilder::new()
+		.enumerate(true)
+		.register::<GlobalContext, _>(GlobalContext::default(), Box::new(HotplugForwarder(sender)))
+		.map_err(|e| Error::usb("register hotplug callback", e))?;
+
+	thread::spawn(handle_usb_events);
+
+	log("info", "daemon_started", &[("settle_ms", &settle_time.as_millis())]);
+
+	let store = ConfigStore::open()?;
+	// devices waiting to settle, keyed by port path
+	let mut pending = HashMap::<String, (Device, Instant)>::new();
+	// devices waiting for their input nodes, keyed by port path
+	let mut remaps = HashMap::<String, PendingRemap>::new();
+	// session files started so far, which are appended to when a device is
+	// reconnected
+	let mut sessions = HashSet::new();
+
+	loop {
+		// signals received while a device was being configured
+		signal::check()?;
+
+		let timeout = pending
+			.values()
+			.map(|(_, deadline)| deadline)
+			.chain(remaps.values().map(|remap| &remap.deadline))
+			.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+			.min()
+			.unwrap_or(Duration::from_secs(3600));
+
+		match receiver.recv_timeout(timeout) {
+			Ok(HotplugEvent::Arrived(device)) => {
+				log("info", "device_arrived", device_fields!(device));
+				pending.insert(device.port_path(), (device, Instant::now() + settle_time));
+			}
+			Ok(HotplugEvent::Left(device)) => {
+				remaps.remove(&device.port_path());
+				match pending.remove(&device.port_path()) {
+					Some(_) => log("warn", "device_flapped", device_fields!(device)),
+					None => log("info", "device_left", device_fields!(device)),
+				}
+			}
+			Err(mpsc::RecvTimeoutError::Timeout) => {}
+			Err(mpsc::RecvTimeoutError::Disconnected) =>
+				return Err(Error::usb("receive hotplug events", rusb::Error::Other)),
+		}
+
+		let now = Instant::now();
+		let settled = pending
+			.iter()
+			.filter(|(_, (_, deadline))| *deadline <= now)
+			.map(|(port, _)| port.clone())
+			.collect::<Vec<_>>();
+
+		for port in settled {
+			// `port` was just read from `pending`
+			let (device, _) = pending.remove(&port).unwrap();
+			let result = flags
+				.session_path(&device)
+				.filter(|path| sessions.insert(path.clone()))
+				.map_or(Ok(()), |path| record::start_session(&path))
+				.and_then(|_| apply_stored_config(&store, &device, flags));
+
+			match result {
+				Ok(config) if !config.software_buttons.is_empty() => {
+					remaps.insert(port, PendingRemap {
+						device,
+						config,
+						attempts: 0,
+						deadline: now,
+					});
+				}
+				Ok(_) => {}
+				Err(e) => log(
+					"error",
+					"config_apply_failed",
+					device_fields!(device, ("error", &e), ("exit_code", &e.exit_code())),
+				),
+			}
+		}
+
+		let due = remaps
+			.iter()
+			.filter(|(_, remap)| remap.deadline <= now)
+			.map(|(port, _)| port.clone())
+			.collect::<Vec<_>>();
+
+		for port in due {
+			// `port` was just read from `remaps`
+			let remap = remaps.remove(&port).unwrap();
+			if let Some(remap) = start_remap(remap) {
+				remaps.insert(port, remap);
+			}
+		}
+	}
+}
@@ -0,0 +1,244 @@
+use std::{
+	fs,
+	io,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+	thread,
+};
+
+use evdev::{
+	uinput::{VirtualDevice, VirtualDeviceBuilder},
+	AbsInfo,
+	AttributeSet,
+	EventType,
+	InputEvent,
+	Key,
+	UinputAbsSetup,
+};
+use glorious_mouse_control::{
+	config::{
+		software::{self, SoftwareAction},
+		Config,
+	},
+	error::Error,
+	usb::device::Device,
+};
+
+use super::log;
+
+/// A software button, as handled on an input node.
+#[derive(Clone)]
+struct Binding {
+	button: String,
+	/// Key code the mouse reports for the button's carrier
+	key_code: u16,
+	action: SoftwareAction,
+}
+
+/// Finds the evdev nodes of `device`, whose sysfs devices are below one of
+/// its USB interfaces (named `<port path>:<config>.<interface>`).
+fn input_nodes(device: &Device) -> Vec<(PathBuf, evdev::Device)> {
+	let interface_prefix = format!("{}:", device.port_path());
+	let belongs_to_device = |node: &Path| {
+		let sysfs = Path::new("/sys/class/input")
+			.join(node.file_name().unwrap_or_default())
+			.join("device");
+		fs::canonicalize(sysfs).is_ok_and(|path| {
+			path.components().any(|part| {
+				part.as_os_str()
+					.to_string_lossy()
+					.starts_with(&interface_prefix)
+			})
+		})
+	};
+
+	evdev::enumerate()
+		.filter(|(path, _)| belongs_to_device(path))
+		.collect()
+}
+
+/// Creates a virtual device named `name` with the same capabilities as
+/// `node`, which can also press `extra_keys`.
+fn virtual_device(
+	name: &str,
+	node: &evdev::Device,
+	extra_keys: &[Key],
+) -> io::Result<VirtualDevice> {
+	let mut keys = node
+		.supported_keys()
+		.map(|keys| keys.iter().collect::<AttributeSet<_>>())
+		.unwrap_or_default();
+	for key in extra_keys {
+		keys.insert(*key);
+	}
+
+	let mut builder = VirtualDeviceBuilder::new()?
+		.name(name)
+		.input_id(node.input_id())
+		.with_keys(&keys)?
+		.with_properties(node.properties())?;
+	if let Some(axes) = node.supported_relative_axes() {
+		builder = builder.with_relative_axes(axes)?;
+	}
+	if let Some(axes) = node.supported_absolute_axes() {
+		let state = node.get_abs_state()?;
+		for axis in axes.iter() {
+			let info = state[axis.0 as usize];
+			let info = AbsInfo::new(
+				info.value,
+				info.minimum,
+				info.maximum,
+				info.fuzz,
+				info.flat,
+				info.resolution,
+			);
+			builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, info))?;
+		}
+	}
+	if let Some(switches) = node.supported_switches() {
+		builder = builder.with_switches(switches)?;
+	}
+	if let Some(misc) = node.misc_properties() {
+		builder = builder.with_msc(misc)?;
+	}
+
+	builder.build()
+}
+
+/// Starts handling the software buttons of `config` for `device`. Every
+/// input node of the mouse which reports a carrier (see
+/// [`software::CARRIERS`]) is grabbed, and its events are passed on through
+/// a virtual device with the same capabilities, except for carrier keys,
+/// which run the action of their button instead. Nodes are handled on their
+/// own threads until the mouse is disconnected. Nothing is grabbed unless
+/// every node can be handled, so starting can be retried.
+///
+/// # Errors
+///
+/// If no input node of the mouse reports a carrier, or a node cannot be
+/// grabbed or its virtual device cannot be created.
+pub fn start(device: &Device, config: &Config) -> Result<(), Error> {
+	let bindings = config
+		.software_bindings()
+		.into_iter()
+		.map(|binding| Binding {
+			button: binding.button.to_owned(),
+			key_code: binding.key_code,
+			action: binding.action.clone(),
+		})
+		.collect::<Vec<_>>();
+
+	// keys pressed by software buttons, which virtual devices must support
+	let action_keys = bindings
+		.iter()
+		.filter_map(|binding| match &binding.action {
+			SoftwareAction::Key(combo) => software::linux_key_codes(combo),
+			SoftwareAction::Command(_) => None,
+		})
+		.flatten()
+		.map(Key::new)
+		.collect::<Vec<_>>();
+
+	// grabs are released if a later node fails, when `handled` is dropped
+	let mut handled = Vec::new();
+	for (path, mut node) in input_nodes(device) {
+		match node.supported_keys() {
+			Some(keys) if bindings.iter().any(|b| keys.contains(Key::new(b.key_code))) => {}
+			_ => continue,
+		}
+
+		let name = format!("glorious-mouse-control {}", node.name().unwrap_or("mouse"));
+		let output = virtual_device(&name, &node, &action_keys).map_err(|e| {
+			Error::io("create a virtual input device (is /dev/uinput writable?)", e)
+		})?;
+
+		node.grab()
+			.map_err(|e| Error::io(format!("grab input device {}", path.display()), e))?;
+		handled.push((path, node, output));
+	}
+
+	if handled.is_empty() {
+		return Err(Error::io(
+			"find the input device of the mouse which reports software buttons",
+			io::Error::from(io::ErrorKind::NotFound),
+		))
+	}
+
+	for (path, mut node, mut output) in handled {
+		let bindings = bindings.clone();
+		thread::spawn(move || {
+			if let Err(e) = forward(&mut node, &mut output, &bindings) {
+				log("info", "remap_stopped", &[("node", &path.display()), ("reason", &e)]);
+			}
+		});
+	}
+	Ok(())
+}
+
+/// Passes the events of `node` on to `output` unchanged until reading fails
+/// (e.g. because the mouse was disconnected), running software button
+/// actions instead of passing on their carrier keys.
+fn forward(
+	node: &mut evdev::Device,
+	output: &mut VirtualDevice,
+	bindings: &[Binding],
+) -> io::Result<()> {
+	// events since the last `SYN_REPORT`, which `emit` sends again
+	let mut batch = Vec::new();
+
+	loop {
+		for event in node.fetch_events()? {
+			let binding = bindings
+				.iter()
+				.find(|binding| binding.key_code == event.code());
+			match (event.event_type(), binding) {
+				(EventType::KEY, Some(binding)) => run(binding, event.value(), output)?,
+				(EventType::SYNCHRONIZATION, _) if !batch.is_empty() => {
+					output.emit(&batch)?;
+					batch.clear();
+				}
+				(EventType::SYNCHRONIZATION, _) => {}
+				_ => batch.push(event),
+			}
+		}
+	}
+}
+
+/// Runs the action of `binding` for a key event with `value` (`1` for a
+/// press, `0` for a release and `2` for a repeat).
+fn run(binding: &Binding, value: i32, output: &mut VirtualDevice) -> io::Result<()> {
+	match (&binding.action, value) {
+		(SoftwareAction::Command(command), 1) => {
+			log("info", "software_button_pressed", &[
+				("button", &binding.button),
+				("command", command),
+			]);
+			let child = Command::new("sh")
+				.arg("-c")
+				.arg(command)
+				.stdin(Stdio::null())
+				.spawn();
+			match child {
+				// reap the command once it exits
+				Ok(mut child) => drop(thread::spawn(move || child.wait())),
+				Err(e) =>
+					log("error", "command_failed", &[("button", &binding.button), ("error", &e)]),
+			}
+		}
+		(SoftwareAction::Key(combo), 0 | 1) => {
+			// checked by `SoftwareAction::validate`
+			let mut codes = software::linux_key_codes(combo).unwrap_or_default();
+			if value == 0 {
+				codes.reverse();
+			}
+			let events = codes
+				.into_iter()
+				.map(|code| InputEvent::new(EventType::KEY, code, value))
+				.collect::<Vec<_>>();
+			output.emit(&events)?;
+		}
+		_ => {}
+	}
+
+	Ok(())
+}
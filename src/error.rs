@@ -0,0 +1,232 @@
+use std::{
+	fmt::{self, Formatter},
+	io,
+	path::PathBuf,
+};
+
+use crate::{
+	config::validate::Problem,
+	usb::{capture::CaptureError, decode::DecodeError},
+};
+
+/// Everything that can go wrong while running a command. Errors are
+/// propagated up to `main`, which prints them and exits with
+/// [`Error::exit_code`].
+#[derive(Debug)]
+pub enum Error {
+	/// No supported mouse is connected.
+	DeviceNotFound,
+	/// No connected mouse matches the `--device` selector.
+	NoMatchingDevice(String),
+	/// Access to the mouse was denied.
+	Permission { action: String, source: rusb::Error },
+	/// An interface could not be claimed, or its kernel driver could not be
+	/// detached.
+	Claim { interface: u8, source: rusb::Error },
+	/// A control transfer failed.
+	Transfer { action: String, source: rusb::Error },
+	/// Any other libusb failure.
+	Usb { action: String, source: rusb::Error },
+	/// A file could not be read or written.
+	Io { action: String, source: io::Error },
+	/// A config file is not valid json, or does not match
+	/// [`crate::config::Config`].
+	ConfigParse {
+		path: PathBuf,
+		source: serde_json::Error,
+	},
+	/// A config file was written by a newer version of the program.
+	ConfigVersion { path: PathBuf, version: u64 },
+	/// A config file contains values which do not match
+	/// [`crate::config::Config`].
+	InvalidConfig {
+		path: PathBuf,
+		problems: Vec<Problem>,
+	},
+	/// Config files checked together contain values which do not match
+	/// [`crate::config::Config`], with the problems of each file.
+	InvalidConfigFiles(Vec<(PathBuf, Vec<Problem>)>),
+	/// A config cannot be applied to a mouse.
+	Validation(Vec<Problem>),
+	/// A packet read from the mouse could not be decoded.
+	Decode(DecodeError),
+	/// A capture file could not be read.
+	Capture(CaptureError),
+	/// The command line asks for something that cannot be done.
+	Usage(String),
+	/// A part of the protocol which has not been confirmed against the
+	/// official software was needed without
+	/// [`crate::usb::Options::experimental`].
+	Unconfirmed(String),
+	/// SIGINT or SIGTERM was received while a device was in use.
+	Interrupted,
+}
+
+impl Error {
+	/// Wraps a libusb failure, separating out permission errors.
+	pub fn usb(action: impl Into<String>, source: rusb::Error) -> Self {
+		match source {
+			rusb::Error::Access => Self::Permission {
+				action: action.into(),
+				source,
+			},
+			_ => Self::Usb {
+				action: action.into(),
+				source,
+			},
+		}
+	}
+
+	/// Wraps a failure to claim `interface`, separating out permission
+	/// errors.
+	pub fn claim(interface: u8, source: rusb::Error) -> Self {
+		match source {
+			rusb::Error::Access => Self::Permission {
+				action: format!("claim interface {interface}"),
+				source,
+			},
+			_ => Self::Claim { interface, source },
+		}
+	}
+
+	pub fn transfer(action: impl Into<String>, source: rusb::Error) -> Self {
+		Self::Transfer {
+			action: action.into(),
+			source,
+		}
+	}
+
+	/// A single [`Problem`] with a config.
+	pub fn validation(path: impl Into<String>, message: impl Into<String>) -> Self {
+		Self::Validation(vec![Problem::new(path, message)])
+	}
+
+	pub fn io(action: impl Into<String>, source: io::Error) -> Self {
+		Self::Io {
+			action: action.into(),
+			source,
+		}
+	}
+
+	/// Process exit code for the error. `2` matches clap's exit code for
+	/// invalid arguments, and `130` the shell's code for SIGINT.
+	pub fn exit_code(&self) -> i32 {
+		match self {
+			Self::Io { .. } => 1,
+			Self::Usage(_) | Self::Unconfirmed(_) => 2,
+			Self::DeviceNotFound | Self::NoMatchingDevice(_) => 3,
+			Self::Permission { .. } => 4,
+			Self::Claim { .. } => 5,
+			Self::Transfer { .. } | Self::Usb { .. } => 6,
+			Self::ConfigParse { .. } | Self::ConfigVersion { .. } => 7,
+			Self::InvalidConfig { .. } | Self::InvalidConfigFiles(_) | Self::Validation(_) => 8,
+			Self::Decode(_) | Self::Capture(_) => 9,
+			Self::Interrupted => 130,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::DeviceNotFound => write!(f, "could not find usb device"),
+			Self::NoMatchingDevice(selector) =>
+				write!(f, "could not find usb device matching `{selector}`"),
+			Self::Permission { action, source } => write!(
+				f,
+				"could not {action}: {source} (run as root, or grant write access to the usb \
+				 device)"
+			),
+			Self::Claim { interface, source } =>
+				write!(f, "could not claim interface {interface}: {source}"),
+			Self::Transfer { action, source } | Self::Usb { action, source } =>
+				write!(f, "could not {action}: {source}"),
+			Self::Io { action, source } => write!(f, "could not {action}: {source}"),
+			Self::ConfigParse { path, source } =>
+				write!(f, "could not parse config file {}: {source}", path.display()),
+			Self::ConfigVersion { path, version } => write!(
+				f,
+				"config file {} has version {version}, but this version of the program only \
+				 supports up to version {}",
+				path.display(),
+				crate::config::migrate::CURRENT_VERSION
+			),
+			Self::InvalidConfig { path, problems } => {
+				write!(f, "config file {} is invalid:", path.display())?;
+				for problem in problems {
+					write!(f, "\n  {problem}")?;
+				}
+				Ok(())
+			}
+			Self::InvalidConfigFiles(files) => {
+				match files.len() {
+					1 => write!(f, "1 config file is invalid:")?,
+					count => write!(f, "{count} config files are invalid:")?,
+				}
+				for (path, problems) in files {
+					write!(f, "\n  {}:", path.display())?;
+					for problem in problems {
+						write!(f, "\n    {problem}")?;
+					}
+				}
+				Ok(())
+			}
+			Self::Validation(problems) => match &problems[..] {
+				[problem] => write!(f, "{problem}"),
+				problems => {
+					write!(f, "the config cannot be applied:")?;
+					for problem in problems {
+						write!(f, "\n  {problem}")?;
+					}
+					Ok(())
+				}
+			},
+			Self::Usage(message) => write!(f, "{message}"),
+			Self::Unconfirmed(action) => write!(
+				f,
+				"{action} is experimental: it has not been confirmed against the official \
+				 software and may change settings on the mouse (pass --experimental to do it \
+				 anyway)"
+			),
+			Self::Decode(e) => write!(f, "could not decode device config: {e}"),
+			Self::Capture(e) => write!(f, "could not read capture: {e}"),
+			Self::Interrupted => write!(f, "interrupted"),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Permission { source, .. }
+			| Self::Claim { source, .. }
+			| Self::Transfer { source, .. }
+			| Self::Usb { source, .. } => Some(source),
+			Self::Io { source, .. } => Some(source),
+			Self::ConfigParse { source, .. } => Some(source),
+			Self::Decode(e) => Some(e),
+			Self::Capture(e) => Some(e),
+			Self::DeviceNotFound
+			| Self::NoMatchingDevice(_)
+			| Self::ConfigVersion { .. }
+			| Self::InvalidConfig { .. }
+			| Self::InvalidConfigFiles(_)
+			| Self::Validation(_)
+			| Self::Usage(_)
+			| Self::Unconfirmed(_)
+			| Self::Interrupted => None,
+		}
+	}
+}
+
+impl From<DecodeError> for Error {
+	fn from(e: DecodeError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+impl From<CaptureError> for Error {
+	fn from(e: CaptureError) -> Self {
+		Self::Capture(e)
+	}
+}
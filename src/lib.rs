@@ -0,0 +1,17 @@
+//! Configuration of Glorious mice over USB.
+//!
+//! [`Config`] holds every setting the mouse supports. It can be applied to
+//! or read from a connected [`Device`], or turned into the raw reports
+//! described in `packet_spec.md` with the encoders in [`usb`]
+//! ([`usb::build_main_packet`] and friends) and back with the decoders in
+//! [`usb::decode`].
+
+pub mod config;
+pub mod error;
+pub mod signal;
+pub mod store;
+pub mod usb;
+
+pub use config::Config;
+pub use error::Error;
+pub use usb::device::Device;
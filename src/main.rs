@@ -1,88 +1,448 @@
-use std::fs;
+use std::path::PathBuf;
 
 use clap::Parser;
+use glorious_mouse_control::{config, error::Error, signal, store, usb, Device};
 
 mod command;
-mod config;
-mod usb;
-
-/// Exit the process nicely
-macro_rules! error {
-	($($arg:tt)*) => {{
-		eprintln!("error: {}", format!($($arg)*));
-		::std::process::exit(1)
-	}}
-}
-pub(crate) use error;
+mod daemon;
 
-fn main() {
-	let cmd = command::Command::parse();
+/// Resolves the config files to use for `target`, returning
+/// `(<load path>, <save path>)`. An explicit `--config` path wins, followed
+/// by the active profile (unless `--config-scope` is used), followed by the
+/// per-device configs.
+///
+/// # Errors
+///
+/// See [`store::ConfigStore::profile_path`] and
+/// [`store::ConfigStore::resolve`]
+fn config_paths(
+	store: &store::ConfigStore,
+	target: &store::ConfigTarget,
+	flags: &command::ExtraFlags,
+) -> Result<(PathBuf, PathBuf), Error> {
+	let active_profile = store
+		.active_profile()
+		.filter(|_| flags.config_scope.is_none());
 
-	// must be destructured to avoid the lifetime disallowing `apply_command_config`
-	let command::ExtraFlags {
-		save_config,
-		use_config,
-		config_location,
-	} = cmd.flags();
-
-	let config_path = config_location
-		.map(|p| std::path::PathBuf::from(p))
-		.unwrap_or_else(|| {
-			xdg::BaseDirectories::with_prefix("glorious-mouse-control")
-				.unwrap_or_else(|e| error!("error getting XDG directories: {e}"))
-				.place_config_file("config.json")
-				.unwrap_or_else(|e| error!("could not create config file: {e}"))
-		});
+	match (flags.config_location, active_profile) {
+		(Some(path), _) => Ok((PathBuf::from(path), PathBuf::from(path))),
+		(None, Some(profile)) => {
+			let path = store.profile_path(&profile)?;
+			Ok((path.clone(), path))
+		}
+		(None, None) => store.resolve(target, flags.config_scope),
+	}
+}
+
+/// Selects the devices to use (see [`usb::device::select_devices`]) and
+/// starts their session files if they are recorded separately.
+///
+/// # Errors
+///
+/// If no device matches, or a session file cannot be created.
+fn select_devices(flags: &command::ExtraFlags, all: bool) -> Result<Vec<Device>, Error> {
+	let devices = usb::device::select_devices(flags.device, all)?;
 
-	let mut merged_config = cmd.apply_command_config(if use_config {
-		match fs::read_to_string(&config_path).ok() {
-			Some(config_json) => serde_json::from_str::<config::Config>(&config_json)
-				.unwrap_or_else(|e| error!("could not parse config file: {e}")),
-			None => config::Config::default(),
+	if flags.per_device_sessions {
+		for path in devices
+			.iter()
+			.filter_map(|device| flags.session_path(device))
+		{
+			usb::record::start_session(&path)?;
 		}
-	} else {
-		config::Config::default()
-	});
-
-	// at least one DPI must be enabled
-	if !merged_config
-		.dpi
-		.iter()
-		.any(|config::Dpi { enable, .. }| *enable)
-	{
-		error!("at least one DPI must be enabled")
 	}
+	Ok(devices)
+}
 
-	// check that the selected dpi is enabled and reset if it isn't
-	if !merged_config.dpi[*merged_config.current_dpi as usize].enable {
-		merged_config.current_dpi = config::RangedByte(
-			merged_config
-				.dpi
-				.iter()
-				.enumerate()
-				.find(|(_, config::Dpi { enable, .. })| *enable)
-				// unwrap will never panic because at least one dpi must be enabled, as asserted above
-				.unwrap()
-				.0 as u8,
-		);
+/// Prints a field-by-field breakdown of the report in `input` (a file or hex
+/// bytes).
+///
+/// # Errors
+///
+/// If the input cannot be read or is not a known report.
+fn dissect_command(input: &str) -> Result<(), Error> {
+	let packet = match std::path::Path::new(input).is_file() {
+		true => std::fs::read(input).map_err(|e| Error::io(format!("read {input}"), e))?,
+		false => usb::layout::parse_hex(input)
+			.map_err(|e| Error::Usage(format!("{input} is not a file or hex data: {e}")))?,
+	};
 
+	let dissection = usb::layout::dissect(&packet).ok_or_else(|| {
+		Error::Usage("not a Type 1, Type 2, Type 3 or debounce report".to_owned())
+	})?;
+	print!("{dissection}");
+	Ok(())
+}
+
+/// Decodes the reports found in the capture `file`, printing the config
+/// after each report or, with `diff`, the fields it changed. With
+/// `fixture`, the last report of each type is saved as a golden test
+/// fixture.
+///
+/// # Errors
+///
+/// If the capture cannot be read, or a fixture cannot be created from it.
+fn import_capture_command(file: &str, diff: bool, fixture: Option<&str>) -> Result<(), Error> {
+	use usb::{capture, layout::PacketType};
+
+	let data = std::fs::read(file).map_err(|e| Error::io(format!("read {file}"), e))?;
+	let reports = capture::read_capture(&data)?;
+
+	if reports.iter().any(|report| report.model.is_none()) {
 		eprintln!(
-			"warning: the selected DPI is not enabled - DPI {} has been selected instead",
-			*merged_config.current_dpi
+			"warning: some devices were connected before the capture started, so their model is \
+			 unknown"
+		);
+	}
+
+	let mut decoder = capture::CaptureDecoder::default();
+	for report in &reports {
+		println!(
+			"t={:.6} bus {} address {} ({}) wValue={:#06x}: {}",
+			report.timestamp,
+			report.bus,
+			report.address,
+			report.model.map_or("unknown model", |model| model.name),
+			report.value,
+			PacketType::identify(&report.data)
+				.map_or("unknown report", |packet_type| packet_type.name())
+		);
+
+		match (decoder.decode(report), diff) {
+			(Ok(None), _) => println!("  {}", usb::layout::format_bytes(&report.data)),
+			(Ok(Some(changes)), true) =>
+				for (path, old, new) in changes {
+					println!("  {path}: {old} -> {new}");
+				},
+			(Ok(Some(_)), false) => {
+				// the report was just decoded into this config
+				let config = decoder.config(report.bus, report.address).unwrap();
+				println!("{}", store::config_json(config)?);
+			}
+			(Err(e), _) => println!("  could not decode report: {e}"),
+		}
+	}
+
+	if let Some(dir) = fixture {
+		decoder.fixture()?.write(std::path::Path::new(dir))?;
+		println!(
+			"fixture written to {dir} (run `cargo test` to check the packet builders against it)"
 		);
 	}
 
-	if save_config {
-		fs::write(
-			&config_path,
-			serde_json::to_string_pretty(&merged_config).unwrap_or_else(|e| {
-				error!("could not create a json representation of the current config: {e}")
-			}),
-		)
-		.unwrap_or_else(|e| error!("could not save config file: {e}"));
+	Ok(())
+}
+
+/// Runs a `profile` subcommand.
+///
+/// # Errors
+///
+/// If the profile does not exist (or already exists when creating one), or
+/// it cannot be loaded, saved or applied.
+fn profile_command(
+	cmd: &command::Command,
+	action: &command::ProfileAction,
+	flags: &command::ExtraFlags,
+) -> Result<(), Error> {
+	use command::ProfileAction;
+
+	let store = store::ConfigStore::open()?;
+	let load_profile = |name: &str| {
+		store::load_config(&store.profile_path(name)?)?
+			.ok_or_else(|| Error::Usage(format!("profile `{name}` does not exist")))
+	};
+	let check_new_profile = |name: &str, force: bool| {
+		let path = store.profile_path(name)?;
+		if path.exists() && !force {
+			return Err(Error::Usage(format!(
+				"profile `{name}` already exists (use --force to replace it)"
+			)))
+		}
+		Ok(path)
+	};
+
+	match action {
+		ProfileAction::List => {
+			let active = store.active_profile();
+			for name in store.profiles() {
+				match Some(&name) == active.as_ref() {
+					true => println!("* {name}"),
+					false => println!("  {name}"),
+				}
+			}
+		}
+		ProfileAction::Show { name } => println!("{}", store::config_json(&load_profile(name)?)?),
+		ProfileAction::Create { name, force } => {
+			let path = check_new_profile(name, *force)?;
+
+			let (config, models) = match flags.from_device {
+				true => {
+					let device = &select_devices(flags, false)?[0];
+					(device.read(&flags.options(device))?, vec![device.model])
+				}
+				// the profile can be switched to on any model
+				false => (config::Config::default(), usb::TARGET_DEVICES.iter().collect()),
+			};
+			let mut config = cmd.clone().apply_command_config(config);
+
+			// a profile is only saved if it can be applied
+			config.check()?;
+			for model in models {
+				model.check_config(&config)?;
+			}
+			usb::check_unconfirmed(&config, flags.experimental)?;
+
+			store::save_config(&path, &config)?;
+			println!("created profile `{name}`");
+		}
+		ProfileAction::Copy { from, to, force } => {
+			let config = load_profile(from)?;
+			store::save_config(&check_new_profile(to, *force)?, &config)?;
+			println!("copied profile `{from}` to `{to}`");
+		}
+		ProfileAction::Delete { name } => {
+			let path = store.profile_path(name)?;
+			if !path.exists() {
+				return Err(Error::Usage(format!("profile `{name}` does not exist")))
+			}
+			std::fs::remove_file(&path)
+				.map_err(|e| Error::io(format!("delete profile `{name}`"), e))?;
+
+			if store.active_profile().as_ref() == Some(name) {
+				store.set_active_profile(None)?;
+			}
+			println!("deleted profile `{name}`");
+		}
+		ProfileAction::Switch { name } => {
+			let mut config = load_profile(name)?;
+			config.check()?;
+
+			for device in select_devices(flags, flags.all)? {
+				device.apply(&config, &flags.options(&device))?;
+				println!("profile `{name}` applied to {device}");
+			}
+
+			store.set_active_profile(Some(name))?;
+		}
+		ProfileAction::Deactivate => {
+			store.set_active_profile(None)?;
+			println!("no profile is active");
+		}
+	}
+
+	Ok(())
+}
+
+/// Runs a `config` subcommand.
+///
+/// # Errors
+///
+/// If a config file cannot be read, parsed or upgraded, or `check` finds
+/// problems.
+fn config_command(
+	action: &command::ConfigAction,
+	flags: &command::ExtraFlags,
+) -> Result<(), Error> {
+	use command::ConfigAction;
+
+	let paths = || -> Result<Vec<PathBuf>, Error> {
+		Ok(match flags.config_location {
+			Some(path) => vec![PathBuf::from(path)],
+			None => store::ConfigStore::open()?.config_files(),
+		})
+	};
+
+	match action {
+		ConfigAction::Migrate { check } =>
+			for path in paths()? {
+				let migration = store::migrate_config(&path, *check)?.ok_or_else(|| {
+					Error::Usage(format!("config file {} does not exist", path.display()))
+				})?;
+
+				match (migration.is_needed(), check) {
+					(false, _) => println!("{}: up to date", path.display()),
+					(true, true) => println!(
+						"{}: would be upgraded from version {}",
+						path.display(),
+						migration.from
+					),
+					(true, false) =>
+						println!("{}: upgraded from version {}", path.display(), migration.from),
+				}
+				for (path, old, new) in migration.changes {
+					println!("  {path}: {old} -> {new}");
+				}
+			},
+		ConfigAction::Check => {
+			let mut invalid = Vec::new();
+			for path in paths()? {
+				let problems = store::check_config_file(&path)?.ok_or_else(|| {
+					Error::Usage(format!("config file {} does not exist", path.display()))
+				})?;
+
+				match problems.is_empty() {
+					true => println!("{}: ok", path.display()),
+					false => {
+						println!("{}: invalid", path.display());
+						invalid.push((path, problems));
+					}
+				}
+			}
+
+			if !invalid.is_empty() {
+				return Err(Error::InvalidConfigFiles(invalid))
+			}
+		}
+		// the schema only contains strings, numbers and defaults which are known to serialize
+		ConfigAction::Schema =>
+			println!("{}", serde_json::to_string_pretty(&config::Config::schema()).unwrap()),
+	}
+
+	Ok(())
+}
+
+/// Runs the command given on the command line.
+///
+/// # Errors
+///
+/// If the command fails. See [`Error`] for the possible failures.
+fn run(cmd: &command::Command) -> Result<(), Error> {
+	let flags = cmd.flags();
+
+	// separate sessions are started once their devices are known
+	if let (Some(path), false) = (flags.record, flags.per_device_sessions) {
+		usb::record::start_session(path)?;
+	}
+
+	match &cmd.action {
+		Some(command::Action::Get) => {
+			if flags.all {
+				return Err(Error::Usage("`get` can only read from one device at a time".to_owned()))
+			}
+
+			for device in select_devices(&flags, false)? {
+				let config = device.read(&flags.options(&device))?;
+				println!("{}", store::config_json(&config)?);
+			}
+			return Ok(())
+		}
+		Some(command::Action::ListDevices) => {
+			for (i, device) in usb::device::find_devices()?.iter().enumerate() {
+				match device.serial() {
+					Some(serial) => println!("{i}: {device} serial {serial}"),
+					None => println!("{i}: {device}"),
+				}
+			}
+			return Ok(())
+		}
+		Some(command::Action::Recover) => {
+			for device in usb::device::select_devices(flags.device, flags.all)? {
+				match usb::recover(&device)?[..] {
+					[] => println!("{device} already has its kernel drivers attached"),
+					ref interfaces =>
+						println!("reattached kernel drivers of {device} interfaces {interfaces:?}"),
+				}
+			}
+			return Ok(())
+		}
+		Some(command::Action::Profile { action }) => return profile_command(cmd, action, &flags),
+		Some(command::Action::Config { action }) => return config_command(action, &flags),
+		Some(command::Action::Dissect { input }) => return dissect_command(input),
+		Some(command::Action::ImportCapture {
+			file,
+			diff,
+			write_fixture,
+		}) => return import_capture_command(file, *diff, write_fixture.as_deref()),
+		Some(command::Action::Daemon { settle_ms }) =>
+			return daemon::run(&flags, std::time::Duration::from_millis(*settle_ms)),
+		None => {}
+	}
+
+	let store = store::ConfigStore::open()?;
+
+	if flags.dry_run {
+		let target = store::ConfigTarget::model(flags.dry_run_model);
+		let (load_path, _) = config_paths(&store, &target, &flags)?;
+
+		let mut merged_config = cmd.clone().apply_command_config(if flags.use_config {
+			store::load_config(&load_path)?.unwrap_or_default()
+		} else {
+			config::Config::default()
+		});
+
+		merged_config.check()?;
+		return usb::dry_run(&merged_config, flags.dry_run_model)
+	}
+
+	for device in select_devices(&flags, flags.all)? {
+		signal::check()?;
+		let (load_path, save_path) =
+			config_paths(&store, &store::ConfigTarget::device(&device), &flags)?;
+
+		let mut merged_config = cmd.clone().apply_command_config(if flags.from_device {
+			device.read(&flags.options(&device))?
+		} else if flags.use_config {
+			store::load_config(&load_path)?.unwrap_or_default()
+		} else {
+			config::Config::default()
+		});
+
+		merged_config.check()?;
+		device.apply(&merged_config, &flags.options(&device))?;
+
+		// only saved once the device has accepted it
+		if flags.save_config {
+			store::save_config(&save_path, &merged_config)?;
+		}
+
+		println!("new configuration applied to {device}");
+	}
+
+	Ok(())
+}
+
+fn main() {
+	let cmd = command::Command::parse();
+	signal::install();
+
+	// a signal received after the last check is still honored
+	if let Err(e) = run(&cmd).and_then(|_| signal::check()) {
+		eprintln!("error: {e}");
+		std::process::exit(e.exit_code())
 	}
+}
 
-	usb::apply_config(&merged_config);
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-	println!("new configuration applied");
+	#[test]
+	fn does_not_save_rejected_profiles() {
+		let config_home = std::env::temp_dir()
+			.join(format!("glorious-mouse-control-{}-profiles", std::process::id()));
+		std::env::set_var("XDG_CONFIG_HOME", &config_home);
+		let create = |args: &[&str]| {
+			let args = ["glorious-mouse-control"]
+				.iter()
+				.chain(args)
+				.chain(&["profile", "create", "rejected"]);
+			run(&command::Command::parse_from(args))
+		};
+		let saved = || {
+			let store = store::ConfigStore::open().unwrap();
+			store.profile_path("rejected").unwrap().exists()
+		};
+
+		assert!(matches!(create(&["--dpi", "0:13000"]), Err(Error::Validation(_))));
+		assert!(!saved());
+		assert!(matches!(create(&["--button", "back:key:ctrl+c"]), Err(Error::Unconfirmed(_))));
+		assert!(!saved());
+		assert!(matches!(create(&["--button", "slot-19:media-mute"]), Err(Error::Validation(_))));
+		assert!(!saved());
+
+		create(&["--dpi", "0:1200"]).unwrap();
+		assert!(saved());
+
+		let _ = std::fs::remove_dir_all(&config_home);
+	}
 }
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::error::Error;
+
+/// Set once SIGINT or SIGTERM is received while a device is held.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Number of live [`DeviceHold`]s.
+static DEVICES_HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs handlers for SIGINT and SIGTERM. If no device is held, the
+/// process exits immediately. Otherwise the signal is recorded and
+/// [`check`] fails at the next opportunity, so interfaces are released and
+/// kernel drivers reattached before exiting.
+pub fn install() {
+	for signal in [libc::SIGINT, libc::SIGTERM] {
+		// SAFETY: `handle` only touches atomics and calls `_exit`, both of
+		// which are async-signal-safe.
+		unsafe {
+			libc::signal(signal, handle as extern "C" fn(libc::c_int) as libc::sighandler_t);
+		}
+	}
+}
+
+extern "C" fn handle(signal: libc::c_int) {
+	if DEVICES_HELD.load(Ordering::SeqCst) == 0 {
+		// SAFETY: `_exit` is async-signal-safe, and nothing needs cleaning up
+		// while no device is held.
+		unsafe { libc::_exit(128 + signal) }
+	}
+
+	INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether SIGINT or SIGTERM was received while a device was held.
+pub fn interrupted() -> bool {
+	INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// # Errors
+///
+/// [`Error::Interrupted`] if [`interrupted`].
+pub fn check() -> Result<(), Error> {
+	match interrupted() {
+		true => Err(Error::Interrupted),
+		false => Ok(()),
+	}
+}
+
+/// Defers SIGINT and SIGTERM until dropped (see [`install`]). Held while
+/// interfaces are claimed.
+pub(crate) struct DeviceHold(());
+
+impl DeviceHold {
+	pub(crate) fn new() -> Self {
+		DEVICES_HELD.fetch_add(1, Ordering::SeqCst);
+		Self(())
+	}
+}
+
+impl Drop for DeviceHold {
+	fn drop(&mut self) {
+		DEVICES_HELD.fetch_sub(1, Ordering::SeqCst);
+	}
+}
@@ -0,0 +1,419 @@
+use std::{
+	fs,
+	io,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	config::{
+		migrate::{self, Migration},
+		validate::Problem,
+		Config,
+	},
+	error::Error,
+	usb::{device::Device, Model, TARGET_DEVICES},
+};
+
+/// How specific the config file used for a device is, from least to most
+/// specific.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ArgEnum)]
+pub enum ConfigScope {
+	/// One config shared by every device (`config.json`)
+	Shared,
+	/// One config per model (`devices/<model>.json`)
+	Model,
+	/// One config per USB port (`devices/<model>@port-<port path>.json`)
+	Port,
+	/// One config per device (`devices/<model>@serial-<serial>.json`)
+	Serial,
+}
+
+/// What the per-device config files of a mouse are named after.
+pub struct ConfigTarget {
+	/// Describes the mouse in errors
+	name: String,
+	model: &'static Model,
+	port_path: Option<String>,
+	serial: Option<String>,
+}
+
+impl ConfigTarget {
+	/// Configs for a connected `device`.
+	pub fn device(device: &Device) -> Self {
+		Self {
+			name: device.to_string(),
+			model: device.model,
+			port_path: Some(device.port_path()),
+			serial: device.serial(),
+		}
+	}
+
+	/// Configs for a `model` which is not connected (e.g. `--dry-run`), which
+	/// has no port or serial number configs.
+	pub fn model(model: &'static Model) -> Self {
+		Self {
+			name: format!("{} (not connected)", model.name),
+			model,
+			port_path: None,
+			serial: None,
+		}
+	}
+}
+
+/// Locates config files under the XDG config directory.
+pub struct ConfigStore {
+	directories: xdg::BaseDirectories,
+}
+
+impl ConfigStore {
+	/// # Errors
+	///
+	/// If the XDG directories cannot be determined.
+	pub fn open() -> Result<Self, Error> {
+		Ok(Self {
+			directories: xdg::BaseDirectories::with_prefix("glorious-mouse-control")
+				.map_err(|e| Error::Usage(format!("error getting XDG directories: {e}")))?,
+		})
+	}
+
+	/// Path of the config file for `target` at `scope`, relative to the
+	/// config directory. Returns [`None`] if `target` has no port path or
+	/// serial number for [`ConfigScope::Port`] or [`ConfigScope::Serial`].
+	fn relative_path(target: &ConfigTarget, scope: ConfigScope) -> Option<String> {
+		let model = target.model.key;
+
+		Some(match scope {
+			ConfigScope::Shared => "config.json".to_owned(),
+			ConfigScope::Model => format!("devices/{model}.json"),
+			ConfigScope::Port =>
+				format!("devices/{model}@port-{}.json", target.port_path.as_ref()?),
+			ConfigScope::Serial => {
+				let serial = target
+					.serial
+					.as_ref()?
+					.chars()
+					.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+					.collect::<String>();
+				format!("devices/{model}@serial-{serial}.json")
+			}
+		})
+	}
+
+	/// Resolves the config files to use for `target`, returning
+	/// `(<load path>, <save path>)`.
+	///
+	/// Configs are loaded from the most specific existing file no more
+	/// specific than `scope` (any scope if [`None`]), falling back to the
+	/// shared config. They are saved at `scope`, or back to the file they
+	/// were loaded from if there is no `scope`.
+	///
+	/// # Errors
+	///
+	/// If `target` has no config at `scope` (a serial number for
+	/// [`ConfigScope::Serial`], or a port for [`ConfigScope::Port`]), or the
+	/// config directory cannot be created.
+	pub fn resolve(
+		&self,
+		target: &ConfigTarget,
+		scope: Option<ConfigScope>,
+	) -> Result<(PathBuf, PathBuf), Error> {
+		let load_path = [
+			ConfigScope::Serial,
+			ConfigScope::Port,
+			ConfigScope::Model,
+			ConfigScope::Shared,
+		]
+		.into_iter()
+		.filter(|candidate| scope.map(|scope| *candidate <= scope).unwrap_or(true))
+		.filter_map(|candidate| Self::relative_path(target, candidate))
+		.map(|path| self.directories.get_config_home().join(path))
+		.find(|path| path.exists());
+		let load_path = match load_path {
+			Some(path) => path,
+			None => self.place(ConfigScope::Shared, "config.json")?,
+		};
+
+		let save_path = match scope {
+			Some(scope) => {
+				let path = Self::relative_path(target, scope).ok_or_else(|| {
+					Error::Usage(match scope {
+						ConfigScope::Port => format!("{} is not connected to a port", target.name),
+						_ => format!("{} does not have a serial number", target.name),
+					})
+				})?;
+				self.place(scope, &path)?
+			}
+			None => load_path.clone(),
+		};
+
+		Ok((load_path, save_path))
+	}
+
+	/// Path of the config shared by every device.
+	pub fn shared_config_path(&self) -> PathBuf {
+		self.directories.get_config_home().join("config.json")
+	}
+
+	fn place(&self, scope: ConfigScope, path: &str) -> Result<PathBuf, Error> {
+		self.directories
+			.place_config_file(path)
+			.map_err(|e| Error::io(format!("create {scope:?} config file"), e))
+	}
+
+	/// Path of the profile named `name`.
+	///
+	/// # Errors
+	///
+	/// If `name` is not a valid profile name, or the profile directory
+	/// cannot be created.
+	pub fn profile_path(&self, name: &str) -> Result<PathBuf, Error> {
+		if name.is_empty()
+			|| !name
+				.chars()
+				.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+		{
+			return Err(Error::Usage(
+				"profile names may only contain letters, numbers, `-` and `_`".to_owned(),
+			))
+		}
+
+		self.directories
+			.place_config_file(format!("profiles/{name}.json"))
+			.map_err(|e| Error::io("create profile directory", e))
+	}
+
+	/// Names of all saved profiles, sorted alphabetically.
+	pub fn profiles(&self) -> Vec<String> {
+		let mut profiles = fs::read_dir(self.directories.get_config_home().join("profiles"))
+			.map(|entries| {
+				entries
+					.filter_map(Result::ok)
+					.filter_map(|entry| {
+						entry
+							.file_name()
+							.to_str()?
+							.strip_suffix(".json")
+							.map(str::to_owned)
+					})
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default();
+
+		profiles.sort();
+		profiles
+	}
+
+	/// Every config file in the config directory: the shared config, then
+	/// the per-device configs, then the profiles.
+	pub fn config_files(&self) -> Vec<PathBuf> {
+		let config_home = self.directories.get_config_home();
+		let json_files = |dir: &str| {
+			let mut files = fs::read_dir(config_home.join(dir))
+				.map(|entries| {
+					entries
+						.filter_map(Result::ok)
+						.map(|entry| entry.path())
+						.filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+						.collect::<Vec<_>>()
+				})
+				.unwrap_or_default();
+			files.sort();
+			files
+		};
+
+		Some(self.shared_config_path())
+			.filter(|path| path.exists())
+			.into_iter()
+			.chain(json_files("devices"))
+			.chain(json_files("profiles"))
+			.collect()
+	}
+
+	/// Name of the active profile, if one has been switched to.
+	pub fn active_profile(&self) -> Option<String> {
+		fs::read_to_string(self.directories.get_config_home().join("active_profile"))
+			.ok()
+			.map(|name| name.trim().to_owned())
+			.filter(|name| !name.is_empty())
+	}
+
+	/// Sets or clears the active profile.
+	///
+	/// # Errors
+	///
+	/// If the active profile cannot be saved.
+	pub fn set_active_profile(&self, name: Option<&str>) -> Result<(), Error> {
+		let path = self
+			.directories
+			.place_config_file("active_profile")
+			.map_err(|e| Error::io("create active profile file", e))?;
+
+		match name {
+			Some(name) => fs::write(&path, format!("{name}\n")),
+			None => fs::remove_file(&path).or_else(|e| match e.kind() {
+				io::ErrorKind::NotFound => Ok(()),
+				_ => Err(e),
+			}),
+		}
+		.map_err(|e| Error::io("save active profile", e))
+	}
+}
+
+/// Loads the config at `path`, returning [`None`] if it does not exist.
+/// Configs written by older versions are upgraded in memory only; the file
+/// is left alone until it is saved or [`migrate_config`] is run.
+///
+/// # Errors
+///
+/// If the config cannot be read, parsed or upgraded.
+pub fn load_config(path: &Path) -> Result<Option<Config>, Error> {
+	let (config, migration) = match read_config(path)? {
+		Some(loaded) => loaded,
+		None => return Ok(None),
+	};
+
+	if migration.is_needed() {
+		eprintln!(
+			"warning: {} uses config version {} (run `config migrate` to upgrade it to {})",
+			path.display(),
+			migration.from,
+			migrate::CURRENT_VERSION
+		);
+	}
+
+	Ok(Some(config))
+}
+
+/// Upgrades the config at `path` to [`migrate::CURRENT_VERSION`], keeping
+/// the old file as `<name>.v<version>.bak`. With `check`, nothing is
+/// written. Returns what was (or would be) changed, or [`None`] if the
+/// config does not exist.
+///
+/// # Errors
+///
+/// If the config cannot be read, parsed, upgraded or saved.
+pub fn migrate_config(path: &Path, check: bool) -> Result<Option<Migration>, Error> {
+	let (config, migration) = match read_config(path)? {
+		Some(loaded) => loaded,
+		None => return Ok(None),
+	};
+
+	if migration.is_needed() && !check {
+		save_migrated(path, &config, &migration)?;
+	}
+
+	Ok(Some(migration))
+}
+
+/// Reads the config at `path`, upgrading it in memory only. Returns
+/// [`None`] if it does not exist.
+///
+/// # Errors
+///
+/// If the config cannot be read, parsed or upgraded, or contains values
+/// which do not match [`Config`] (all of which are reported).
+pub fn read_config(path: &Path) -> Result<Option<(Config, Migration)>, Error> {
+	match parse_config(path)? {
+		Some((config, migration, problems)) if problems.is_empty() => Ok(Some((config, migration))),
+		Some((.., problems)) => Err(Error::InvalidConfig {
+			path: path.to_owned(),
+			problems,
+		}),
+		None => Ok(None),
+	}
+}
+
+/// Reads and upgrades the config at `path`, along with the values which do
+/// not match [`Config`] (see [`Config::from_json`]).
+fn parse_config(path: &Path) -> Result<Option<(Config, Migration, Vec<Problem>)>, Error> {
+	let config_json = match fs::read_to_string(path) {
+		Ok(config_json) => config_json,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(Error::io(format!("read config file {}", path.display()), e)),
+	};
+	let parse_error = |source| Error::ConfigParse {
+		path: path.to_owned(),
+		source,
+	};
+
+	let mut json = serde_json::from_str(&config_json).map_err(parse_error)?;
+	let migration = migrate::migrate(&mut json).map_err(|e| Error::ConfigVersion {
+		path: path.to_owned(),
+		version: e.0,
+	})?;
+	let (config, problems) = Config::from_json(json);
+
+	Ok(Some((config, migration, problems)))
+}
+
+/// Finds every problem with the config at `path` without changing it (see
+/// [`Config::from_json`] and [`Config::validate`]). Per-device configs are
+/// also checked against the limits of their model. Returns [`None`] if the
+/// config does not exist.
+///
+/// # Errors
+///
+/// If the config cannot be read, is not valid json, or was written by a
+/// newer version.
+pub fn check_config_file(path: &Path) -> Result<Option<Vec<Problem>>, Error> {
+	let (config, _, mut problems) = match parse_config(path)? {
+		Some(parsed) => parsed,
+		None => return Ok(None),
+	};
+
+	problems.extend(config.validate());
+
+	// per-device configs are named `<model>.json` or `<model>@<...>.json`
+	let in_devices = path.parent().and_then(Path::file_name) == Some("devices".as_ref());
+	let model_key = path
+		.file_stem()
+		.and_then(|stem| stem.to_str()?.split('@').next());
+	let model = TARGET_DEVICES
+		.iter()
+		.find(|model| in_devices && Some(model.key) == model_key);
+	if let Some(model) = model {
+		problems.extend(model.validate(&config));
+	}
+
+	Ok(Some(problems))
+}
+
+/// Path the config at `path` is backed up to before upgrading it from
+/// version `version`.
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+	let mut name = path.file_name().unwrap_or_default().to_owned();
+	name.push(format!(".v{version}.bak"));
+	path.with_file_name(name)
+}
+
+/// Backs up the config at `path`, then replaces it with the upgraded
+/// `config`.
+fn save_migrated(path: &Path, config: &Config, migration: &Migration) -> Result<(), Error> {
+	let backup = backup_path(path, migration.from);
+	fs::copy(path, &backup)
+		.map_err(|e| Error::io(format!("back up config file to {}", backup.display()), e))?;
+
+	save_config(path, config)
+}
+
+/// Creates the json representation of `config` which is saved to config
+/// files.
+///
+/// # Errors
+///
+/// If a value in the config is out of range.
+pub fn config_json(config: &Config) -> Result<String, Error> {
+	serde_json::to_string_pretty(config).map_err(|e| {
+		Error::validation("", format!("could not create a json representation of the config: {e}"))
+	})
+}
+
+/// Saves `config` to `path`.
+///
+/// # Errors
+///
+/// If the config cannot be serialized or written.
+pub fn save_config(path: &Path, config: &Config) -> Result<(), Error> {
+	fs::write(path, config_json(config)?)
+		.map_err(|e| Error::io(format!("save config file {}", path.display()), e))
+}
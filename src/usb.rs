@@ -1,70 +1,343 @@
 use std::{
+	collections::HashMap,
 	io::{self, Write},
-	ops::Deref,
+	ops::{Deref, DerefMut},
+	path::PathBuf,
 	time::Duration,
 };
 
-use rusb::{Device, DeviceHandle};
-
+use self::{device::Device, transport::Transport};
 use crate::{
-	config::{self, Color, Dpi, MouseButtonType, RangedByte},
-	error,
+	config::{
+		self,
+		buttons::ButtonAction,
+		macros::{Macro, MacroRepeat, MacroStep},
+		validate::Problem,
+		Color,
+		Dpi,
+		MouseButtonType,
+		RangedByte,
+	},
+	error::Error,
+	signal,
 };
 
-/// List of USB devices to look for `(<vendor id>, <product id>)`.
-static TARGET_DEVICES: &[(u16, u16)] = &[
-	(0x258a, 0x0033), // Model D
-	(0x258a, 0x0036), // Model O
+pub mod capture;
+pub mod decode;
+pub mod device;
+pub mod fixture;
+pub mod layout;
+pub mod mock;
+pub mod record;
+pub mod transport;
+
+/// DPI values are sent to the mouse in units of this many DPI.
+pub const DPI_UNIT: u16 = 100;
+
+/// Every DPI value which fits in a packet. Models support a subset of this
+/// (see [`Model::dpi`]).
+pub const PACKET_DPI: DpiRange = DpiRange {
+	min: DPI_UNIT,
+	max: u8::MAX as u16 * DPI_UNIT,
+	step: DPI_UNIT,
+};
+
+/// Range of DPI values supported by a sensor.
+#[derive(Debug)]
+pub struct DpiRange {
+	pub min: u16,
+	pub max: u16,
+	/// Every DPI value must be a multiple of this
+	pub step: u16,
+}
+
+impl DpiRange {
+	/// Describes why `dpi` is outside of the range, where `name` is what
+	/// the range belongs to.
+	pub fn check(&self, dpi: u16, name: &str) -> Result<(), String> {
+		if dpi > self.max {
+			Err(format!(
+				"{} exceeds {name} maximum of {}",
+				format_dpi(dpi),
+				format_dpi(self.max)
+			))
+		} else if dpi < self.min {
+			Err(format!(
+				"{} is below {name} minimum of {}",
+				format_dpi(dpi),
+				format_dpi(self.min)
+			))
+		} else if dpi % self.step != 0 {
+			Err(format!("{} is not a multiple of {name} step of {}", format_dpi(dpi), self.step))
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Formats `dpi` with a space between each group of thousands.
+pub fn format_dpi(dpi: u16) -> String {
+	let digits = dpi.to_string();
+	let mut formatted = String::new();
+	for (i, digit) in digits.chars().enumerate() {
+		if i != 0 && (digits.len() - i) % 3 == 0 {
+			formatted.push(' ');
+		}
+		formatted.push(digit);
+	}
+	formatted
+}
+
+/// Number of button slots in the `Type 2` packet.
+pub const BUTTON_SLOTS: usize = 19;
+
+/// Name of button slot `slot` in configs, which can be used for any slot
+/// (see [`Model::button_slot`]).
+pub fn slot_name(slot: usize) -> String {
+	format!("slot-{slot}")
+}
+
+/// A physical button of a model.
+#[derive(Debug)]
+pub struct ButtonSlot {
+	/// Name of the button in configs
+	pub name: &'static str,
+	/// Action of the button when the config does not set one
+	pub default: MouseButtonType,
+}
+
+/// A supported mouse model.
+#[derive(Debug)]
+pub struct Model {
+	pub vendor_id: u16,
+	pub product_id: u16,
+	pub name: &'static str,
+	/// Identifier used in config file names
+	pub key: &'static str,
+	/// DPI values supported by the sensor
+	pub dpi: DpiRange,
+	/// Physical buttons, in the order of their slots in the `Type 2` packet
+	/// (starting at slot 0). The remaining slots are disabled unless a
+	/// config sets them.
+	pub buttons: &'static [ButtonSlot],
+}
+
+impl Model {
+	/// Finds the slot of the button called `name`: either one of the
+	/// model's [`Model::buttons`], or `slot-<n>` for any slot.
+	pub fn button_slot(&self, name: &str) -> Option<usize> {
+		match self.buttons.iter().position(|button| button.name == name) {
+			Some(slot) => Some(slot),
+			None => name
+				.strip_prefix("slot-")
+				.and_then(|slot| slot.parse().ok())
+				.filter(|slot| *slot < BUTTON_SLOTS),
+		}
+	}
+
+	/// Name of slot `slot` in configs: the name of the button in that slot,
+	/// or `slot-<n>` if the model has no such button.
+	pub fn button_name(&self, slot: usize) -> String {
+		match self.buttons.get(slot) {
+			Some(button) => button.name.to_owned(),
+			None => slot_name(slot),
+		}
+	}
+
+	/// Action of slot `slot` when the config does not set one.
+	pub fn default_button(&self, slot: usize) -> ButtonAction {
+		match self.buttons.get(slot) {
+			Some(button) => button.default.into(),
+			None => MouseButtonType::Disable.into(),
+		}
+	}
+
+	/// Action of every slot of the `Type 2` packet, with the buttons of
+	/// `config` applied over the defaults and software buttons bound to
+	/// their carriers (see [`config::Config::software_bindings`]). Names
+	/// which are not buttons of the model are skipped (see
+	/// [`Model::validate`]).
+	pub fn button_actions(&self, config: &config::Config) -> [ButtonAction; BUTTON_SLOTS] {
+		let mut actions = std::array::from_fn(|slot| self.default_button(slot));
+		for (name, action) in &config.buttons.0 {
+			if let Some(slot) = self.button_slot(name) {
+				actions[slot] = *action;
+			}
+		}
+		for binding in config.software_bindings() {
+			if let Some(slot) = self.button_slot(binding.button) {
+				actions[slot] = binding.carrier.into();
+			}
+		}
+		actions
+	}
+
+	/// Finds every DPI in `config` which is not supported by the model, and
+	/// every button which the model does not have.
+	pub fn validate(&self, config: &config::Config) -> Vec<Problem> {
+		let mut problems = Vec::new();
+		for (i, Dpi { x_dpi, y_dpi, .. }) in config.dpi.iter().enumerate() {
+			for (axis, dpi) in [("x_dpi", x_dpi), ("y_dpi", y_dpi)] {
+				if let Err(e) = self.dpi.check(*dpi, self.name) {
+					problems.push(Problem::new(format!("dpi[{i}].{axis}"), e));
+				}
+			}
+		}
+
+		let mut set_slots = HashMap::new();
+		let names = config.buttons.0.keys().map(|name| ("buttons", name)).chain(
+			config
+				.software_buttons
+				.keys()
+				.map(|name| ("software_buttons", name)),
+		);
+		for (field, name) in names {
+			match self.button_slot(name) {
+				Some(slot) =>
+					if let Some(other) = set_slots.insert(slot, name) {
+						problems.push(Problem::new(
+							format!("{field}.{name}"),
+							format!("slot {slot} is already set by `{other}`"),
+						));
+					},
+				None => problems.push(Problem::new(
+					format!("{field}.{name}"),
+					format!(
+						"the {} has no button `{name}` (expected {}, or slot-0 to slot-{})",
+						self.name,
+						self.buttons
+							.iter()
+							.map(|button| button.name)
+							.collect::<Vec<_>>()
+							.join(", "),
+						BUTTON_SLOTS - 1
+					),
+				)),
+			}
+		}
+
+		problems
+	}
+
+	/// Checks that every DPI and button in `config` is supported by the
+	/// model.
+	///
+	/// # Errors
+	///
+	/// If any DPI is out of range or a button does not exist (see
+	/// [`Model::validate`]).
+	pub fn check_config(&self, config: &config::Config) -> Result<(), Error> {
+		match self.validate(config) {
+			problems if problems.is_empty() => Ok(()),
+			problems => Err(Error::Validation(problems)),
+		}
+	}
+}
+
+/// Buttons of the Model D and Model O, which share a layout.
+static MODEL_D_O_BUTTONS: &[ButtonSlot] = &[
+	ButtonSlot {
+		name: "left",
+		default: MouseButtonType::LeftClick,
+	},
+	ButtonSlot {
+		name: "right",
+		default: MouseButtonType::RightClick,
+	},
+	ButtonSlot {
+		name: "middle",
+		default: MouseButtonType::MiddleClick,
+	},
+	ButtonSlot {
+		name: "back",
+		default: MouseButtonType::Back,
+	},
+	ButtonSlot {
+		name: "forward",
+		default: MouseButtonType::Forward,
+	},
+	ButtonSlot {
+		name: "dpi",
+		default: MouseButtonType::DpiLoop,
+	},
 ];
 
-/// Finds a device matching one of [`TARGET_DEVICES`].
-///
-/// # Panics
-///
-/// If no target device is connected, or there is an error finding it,
-/// the program will exit with an error message.
-fn find_device() -> Device<rusb::GlobalContext> {
-	rusb::devices()
-		.unwrap_or_else(|e| error!("could not load usb device list: {e}"))
-		.iter()
-		.find(|device| {
-			let descriptor = device
-				.device_descriptor()
-				.unwrap_or_else(|e| error!("could not get device descriptor: {e}"));
-			let id = (descriptor.vendor_id(), descriptor.product_id());
-			TARGET_DEVICES.iter().any(|target| &id == target)
-		})
-		.unwrap_or_else(|| error!("could not find usb device"))
+/// List of USB devices to look for.
+pub static TARGET_DEVICES: &[Model] = &[
+	// both use the PixArt PMW3360 sensor
+	Model {
+		vendor_id: 0x258a,
+		product_id: 0x0033,
+		name: "Model D",
+		key: "model-d",
+		dpi: DpiRange {
+			min: 100,
+			max: 12000,
+			step: 100,
+		},
+		buttons: MODEL_D_O_BUTTONS,
+	},
+	Model {
+		vendor_id: 0x258a,
+		product_id: 0x0036,
+		name: "Model O",
+		key: "model-o",
+		dpi: DpiRange {
+			min: 100,
+			max: 12000,
+			step: 100,
+		},
+		buttons: MODEL_D_O_BUTTONS,
+	},
+];
+
+/// Converts `dpi` to the value sent to the mouse, where `path` names the
+/// field it came from.
+fn dpi_byte(dpi: u16, path: impl FnOnce() -> String) -> Result<u8, Error> {
+	PACKET_DPI
+		.check(dpi, "the protocol")
+		.map_err(|e| Error::validation(path(), e))?;
+
+	Ok((dpi / DPI_UNIT) as u8)
 }
 
 /// Builds a packet matching the `Type 1` section of the `Main Packet`
 /// section of `packet_spec.md`. This packet controls DPI values, DPI states,
 /// DPI colors, selected DPI, liftoff distance, and RGB.
 ///
-/// # Panics
+/// # Errors
 ///
 /// If there is an error writing to the command array (probably won't happen),
-/// or the selected DPI is not enabled (should be checked before `config` is
-/// passed here), the program will exit with an error message.
-fn build_main_packet(config: &config::Config) -> [u8; 520] {
+/// the selected DPI is not enabled (should be checked before `config` is
+/// passed here), or a DPI does not fit in the packet (see [`PACKET_DPI`]).
+pub fn build_main_packet(config: &config::Config) -> Result<[u8; 520], Error> {
 	let mut data = io::Cursor::new([0u8; 520]);
 	macro_rules! write {
 		($buf:expr, [$($data:tt)*]) => {
-			$buf.write_all(&[$($data)*]).unwrap_or_else(|e| error!("error writing usb command to buffer: {e}"))
+			$buf.write_all(&[$($data)*]).map_err(|e| Error::io("write usb command to buffer", e))?
 		};
 		[$($data:tt)*] => {
 			write!(data, [$($data)*])
 		};
 	}
 
-	let write_color =
-		|data: &mut io::Cursor<[u8; 520]>, &Color { r, g, b }| write!(data, [r, b, g]);
+	let write_color = |data: &mut io::Cursor<[u8; 520]>, &Color { r, g, b }| {
+		write!(data, [r, b, g]);
+		Ok::<_, Error>(())
+	};
+	let write_raw = |data: &mut io::Cursor<[u8; 520]>, bytes: &[u8]| {
+		data.write_all(bytes)
+			.map_err(|e| Error::io("write usb command to buffer", e))
+	};
 	let combine_brightness_speed =
 		|brightness: &RangedByte<1, 4>, speed: &RangedByte<1, 3>| (**brightness << 4) | **speed;
 
+	let raw = &config.raw;
+
+	write![0x04, 0x11];
+
 	// unknown data
-	#[rustfmt::skip]
-	write![0x04, 0x11, 0x00, 0x7b, 0x00, 0x00, 0x00, 0x00, 0x64, 0x06];
+	write_raw(&mut data, &raw.header.0)?;
 
 	let separate_xy_dpi = config
 		.dpi
@@ -96,24 +369,27 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 
 	let current_dpi = match current_dpi {
 		Some(current_dpi) => current_dpi,
-		None => error!("selected DPI is not enabled"),
+		None => return Err(Error::validation("current_dpi", "the selected DPI is not enabled")),
 	};
 
 	write![(current_dpi << 4) | dpi_count, flags];
 
 	if separate_xy_dpi {
-		for Dpi { x_dpi, y_dpi, .. } in &config.dpi {
-			write![*x_dpi, *y_dpi];
+		for (i, Dpi { x_dpi, y_dpi, .. }) in config.dpi.iter().enumerate() {
+			write![
+				dpi_byte(*x_dpi, || format!("dpi[{i}].x_dpi"))?,
+				dpi_byte(*y_dpi, || format!("dpi[{i}].y_dpi"))?
+			];
 		}
 	} else {
-		for Dpi { x_dpi: dpi, .. } in &config.dpi {
-			write![*dpi];
+		for (i, Dpi { x_dpi: dpi, .. }) in config.dpi.iter().enumerate() {
+			write![dpi_byte(*dpi, || format!("dpi[{i}].x_dpi"))?];
 		}
 		write![0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
 	}
 
 	// unknown data
-	write![0x00, 0x00, 0x00, 0x00];
+	write_raw(&mut data, &raw.after_dpi.0)?;
 
 	// DPI colors
 	for Dpi {
@@ -124,11 +400,11 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 		write![*r, *g, *b];
 	}
 
+	// unknown data
+	write_raw(&mut data, &raw.after_dpi_colors.0)?;
+
 	#[rustfmt::skip]
 	write![
-		// unknown data
-		0x00, 0x00, 0x00, 0x00, 0x00,  0x00,
-		
 		// LED mode
 		config.lighting.mode as u8,
 		
@@ -142,14 +418,14 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 	];
 
 	// Solid color
-	write_color(&mut data, &config.lighting.solid.color);
+	write_color(&mut data, &config.lighting.solid.color)?;
 
 	// unknown data
-	write![0x42, 0x07];
+	write_raw(&mut data, &raw.before_breathing.0)?;
 
 	// Breathing colors
 	for color in &config.lighting.breathing.colors {
-		write_color(&mut data, color);
+		write_color(&mut data, color)?;
 	}
 
 	#[rustfmt::skip]
@@ -162,12 +438,13 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 		
 		// Fade speed
 		0x40 | *config.lighting.fade.speed,
-		
-		// unknown data (probably tail/fade colors or some combination thereof)
-		                                                       0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00,
-		0x00, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0xff,  0xff, 0xff, 0xff, 0xff, 0xfa, 0x00, 0xff, 0xff,
-		0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00,
-		
+	];
+
+	// unknown data (probably tail/fade colors or some combination thereof)
+	write_raw(&mut data, &raw.after_fade.0)?;
+
+	#[rustfmt::skip]
+	write![
 		// Rave brightness / speed
 		combine_brightness_speed(
 			&config.lighting.rave.brightness,
@@ -176,14 +453,14 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 	];
 
 	for color in &config.lighting.rave.colors {
-		write_color(&mut data, color);
+		write_color(&mut data, color)?;
 	}
 
+	// unknown data
+	write_raw(&mut data, &raw.before_wave.0)?;
+
 	#[rustfmt::skip]
 	write![
-		// unknown data
-		0x02,
-		
 		// Wave brightness / speed
 		combine_brightness_speed(
 			&config.lighting.wave.brightness,
@@ -195,57 +472,103 @@ fn build_main_packet(config: &config::Config) -> [u8; 520] {
 	];
 
 	// Breathing (Single) color
-	write_color(&mut data, &config.lighting.breathing_single.color);
+	write_color(&mut data, &config.lighting.breathing_single.color)?;
 
 	// Liftoff distance
 	write![config.liftoff_distance as u8];
 
-	data.into_inner()
+	Ok(data.into_inner())
 }
 
 /// Builds a packet matching the `Type 2` section of the `Main Packet` section
-/// of `packet_spec.md`. This packet controls mouse button actions.
+/// of `packet_spec.md`. This packet controls mouse button actions, with
+/// button names resolved to slots by `model` (see [`Model::button_actions`]).
 ///
-/// # Panics
+/// # Errors
 ///
 /// If there is an error writing to the command array (probably won't happen)
-fn build_buttons_packet(config: &config::Config) -> [u8; 520] {
+pub fn build_buttons_packet(config: &config::Config, model: &Model) -> Result<[u8; 520], Error> {
 	let mut data = io::Cursor::new([0u8; 520]);
 	macro_rules! write {
 		($buf:expr, [$($data:tt)*]) => {
-			$buf.write_all(&[$($data)*]).unwrap_or_else(|e| error!("error writing usb command to buffer: {e}"))
+			$buf.write_all(&[$($data)*]).map_err(|e| Error::io("write usb command to buffer", e))?
 		};
 		[$($data:tt)*] => {
 			write!(data, [$($data)*])
 		};
 	}
 
-	let write_button = |data: &mut io::Cursor<[u8; 520]>, button: &config::MouseButtonType| {
-		data.write_all(&u32::to_be_bytes(*button as u32))
-			.unwrap_or_else(|e| error!("error writing usb command to buffer: {e}"));
+	let write_button = |data: &mut io::Cursor<[u8; 520]>, button: &ButtonAction| {
+		data.write_all(&button.code())
+			.map_err(|e| Error::io("write usb command to buffer", e))
 	};
 
 	// unknown data
 	write![0x04, 0x12, 0x00, 0x50, 0x00, 0x00, 0x00, 0x00];
 
-	write_button(&mut data, &config.buttons.left);
-	write_button(&mut data, &config.buttons.right);
-	write_button(&mut data, &config.buttons.middle);
-	write_button(&mut data, &config.buttons.back);
-	write_button(&mut data, &config.buttons.forward);
-	write_button(&mut data, &config.buttons.dpi);
+	for action in model.button_actions(config) {
+		write_button(&mut data, &action)?;
+	}
+
+	Ok(data.into_inner())
+}
+
+/// Builds a packet matching the `Type 3` section of the `Main Packet` section
+/// of `packet_spec.md`. This packet stores `macro_` in macro slot `slot`.
+///
+/// # Errors
+///
+/// If there is an error writing to the command array (probably won't happen),
+/// or the macro does not fit on the mouse (see [`Macro::validate`]).
+pub fn build_macro_packet(slot: u8, macro_: &Macro) -> Result<[u8; 520], Error> {
+	match macro_.validate(&format!("macros.{slot}")) {
+		problems if problems.is_empty() => {}
+		problems => return Err(Error::Validation(problems)),
+	}
+
+	let mut data = io::Cursor::new([0u8; 520]);
+	let mut write = |bytes: &[u8]| {
+		data.write_all(bytes)
+			.map_err(|e| Error::io("write usb command to buffer", e))
+	};
+
+	let steps = macro_.steps();
+	let (repeat_mode, repeat_count) = match macro_.repeat {
+		MacroRepeat::Times(times) => (0x01, times),
+		MacroRepeat::WhileHeld => (0x02, 0x00),
+		MacroRepeat::Toggle => (0x03, 0x00),
+	};
+
+	write(&[
+		0x04,
+		0x13,
+		slot,
+		repeat_mode,
+		repeat_count,
+		steps.len() as u8,
+		0x00,
+		0x00,
+	])?;
 
-	for _ in 0..13 {
-		write_button(&mut data, &MouseButtonType::Disable);
+	for (event, delay) in steps {
+		let (kind, code) = match event {
+			MacroStep::KeyDown(key) => (0x81, key.0),
+			MacroStep::KeyUp(key) => (0x01, key.0),
+			MacroStep::ButtonDown(button) => (0x82, button as u8),
+			MacroStep::ButtonUp(button) => (0x02, button as u8),
+		};
+		// delays are checked by `Macro::validate`
+		write(&[kind, code])?;
+		write(&(delay as u16).to_be_bytes())?;
 	}
 
-	data.into_inner()
+	Ok(data.into_inner())
 }
 
 /// Builds a packet matching the `Debounce Packet` section of `packet_spec.md`.
 /// This packet controls debounce time.
 #[rustfmt::skip]
-fn build_debounce_packet(config: &config::Config) -> [u8; 6] {
+pub fn build_debounce_packet(config: &config::Config) -> [u8; 6] {
 	[
 		0x05, 0x1a, // unknown data
 		config.debounce_time as u8,
@@ -253,76 +576,517 @@ fn build_debounce_packet(config: &config::Config) -> [u8; 6] {
 	]
 }
 
+/// Interfaces claimed to configure the mouse.
+const INTERFACES: [u8; 2] = [0, 1];
+
 /// Manages claiming and release of usb device interfaces. (claimed
-/// interfaces will be released and reattached to the kernel (if applicable)
-/// once this struct is dropped)
-struct InterfaceScopeWrapper<'h, const N: usize> {
-	interfaces: [(u8, bool); N],
-	handle: &'h mut DeviceHandle<rusb::GlobalContext>,
+/// interfaces will be released once this struct is dropped, including
+/// while unwinding from a panic, which reattaches the kernel driver if the
+/// transport detached it)
+///
+/// SIGINT and SIGTERM are deferred while interfaces are claimed (see
+/// [`signal::DeviceHold`]).
+struct InterfaceScopeWrapper<'t, T: Transport, const N: usize> {
+	interfaces: [u8; N],
+	transport: &'t mut T,
+	// dropped after the interfaces are released
+	_hold: signal::DeviceHold,
 }
 
-impl<'h, const N: usize> InterfaceScopeWrapper<'h, N> {
-	fn wrap(handle: &'h mut DeviceHandle<rusb::GlobalContext>, interfaces: [u8; N]) -> Self {
-		Self {
-			interfaces: interfaces.map(|interface| {
-				let attached = rusb::supports_detach_kernel_driver()
-					&& handle.kernel_driver_active(interface).unwrap();
-				if attached {
-					handle.detach_kernel_driver(interface).unwrap();
-				}
-				handle.claim_interface(interface).unwrap();
+impl<'t, T: Transport, const N: usize> InterfaceScopeWrapper<'t, T, N> {
+	/// Claims every interface in `interfaces`. If one cannot be claimed,
+	/// the interfaces claimed before it are released again.
+	fn wrap(transport: &'t mut T, interfaces: [u8; N]) -> Result<Self, Error> {
+		let hold = signal::DeviceHold::new();
 
-				(interface, attached)
-			}),
-			handle,
+		for (i, &interface) in interfaces.iter().enumerate() {
+			if let Err(e) = transport.claim_interface(interface) {
+				for &claimed in &interfaces[..i] {
+					release_or_warn(transport, claimed);
+				}
+				return Err(Error::claim(interface, e))
+			}
 		}
+
+		Ok(Self {
+			interfaces,
+			transport,
+			_hold: hold,
+		})
 	}
 }
 
-impl<'h, const N: usize> Drop for InterfaceScopeWrapper<'h, N> {
+/// Releases `interface`, printing a warning if that fails since there is
+/// nothing else to be done about it.
+fn release_or_warn<T: Transport>(transport: &mut T, interface: u8) {
+	if let Err(e) = transport.release_interface(interface) {
+		eprintln!("warning: could not release interface {interface}: {e}");
+	}
+}
+
+impl<'t, T: Transport, const N: usize> Drop for InterfaceScopeWrapper<'t, T, N> {
 	fn drop(&mut self) {
-		for (interface, attached) in self.interfaces {
-			self.handle.release_interface(interface).unwrap();
-			if attached {
-				self.handle.attach_kernel_driver(interface).unwrap();
-			}
+		for interface in self.interfaces {
+			release_or_warn(self.transport, interface);
 		}
 	}
 }
 
-impl<'h, const N: usize> Deref for InterfaceScopeWrapper<'h, N> {
-	type Target = DeviceHandle<rusb::GlobalContext>;
+impl<'t, T: Transport, const N: usize> Deref for InterfaceScopeWrapper<'t, T, N> {
+	type Target = T;
 
 	fn deref(&self) -> &Self::Target {
-		self.handle
+		self.transport
 	}
 }
 
-/// Applies the specified `config` to a connected target device.
+impl<'t, T: Transport, const N: usize> DerefMut for InterfaceScopeWrapper<'t, T, N> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		self.transport
+	}
+}
+
+/// How [`Device::apply`] and [`Device::read`] talk to a device.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+	/// Session file to append every transfer to (see
+	/// [`record::RecordingTransport`])
+	pub record: Option<PathBuf>,
+	/// Use parts of the protocol which have not been confirmed against the
+	/// official software (see [`allow_unconfirmed`])
+	pub experimental: bool,
+}
+
+/// Checks whether `action`, which has not been confirmed against the
+/// official software, may be done, warning if it is.
 ///
-/// # Panics
+/// # Errors
 ///
-/// See [`build_main_packet`]
-pub fn apply_config(config: &config::Config) {
-	let device = find_device();
+/// If `experimental` is not set.
+pub fn allow_unconfirmed(action: &str, experimental: bool) -> Result<(), Error> {
+	if !experimental {
+		return Err(Error::Unconfirmed(action.to_owned()))
+	}
 
-	let main_packet = build_main_packet(config);
-	let buttons_packet = build_buttons_packet(config);
-	let debounce_packet = build_debounce_packet(config);
+	eprintln!(
+		"warning: {action} has not been confirmed against the official software and may change \
+		 settings on the mouse"
+	);
+	Ok(())
+}
 
-	let mut handle = device
-		.open()
-		.unwrap_or_else(|e| error!("could not open usb device: {e}"));
+/// Checks that the parts of `config` which are sent using codes that have
+/// not been confirmed against the official software (keys and macros bound
+/// to buttons, and the `Type 3` macro packets) are allowed (see
+/// [`allow_unconfirmed`]).
+///
+/// # Errors
+///
+/// If `config` uses such a code and `experimental` is not set.
+pub fn check_unconfirmed(config: &config::Config, experimental: bool) -> Result<(), Error> {
+	if config
+		.buttons
+		.0
+		.values()
+		.any(|action| action.is_unconfirmed())
+	{
+		allow_unconfirmed("binding keys or macros to buttons", experimental)?;
+	}
+	if !config.macros.is_empty() {
+		allow_unconfirmed("storing macros on the mouse", experimental)?;
+	}
+
+	Ok(())
+}
+
+/// Sends the packets for `config` over `transport`, which is connected to a
+/// `model`.
+///
+/// # Errors
+///
+/// If the interfaces cannot be claimed, a transfer fails, the program is
+/// interrupted (see [`signal::check`]), or see [`build_main_packet`]
+pub fn write_config<T: Transport>(
+	transport: &mut T,
+	config: &config::Config,
+	model: &Model,
+) -> Result<(), Error> {
+	let main_packet = build_main_packet(config)?;
+	let buttons_packet = build_buttons_packet(config, model)?;
+	let debounce_packet = build_debounce_packet(config);
+	let macro_packets = config
+		.macros
+		.iter()
+		.map(|(slot, macro_)| build_macro_packet(*slot, macro_))
+		.collect::<Result<Vec<_>, _>>()?;
 
-	let handle = InterfaceScopeWrapper::wrap(&mut handle, [0, 1]);
+	signal::check()?;
+	let mut handle = InterfaceScopeWrapper::wrap(transport, INTERFACES)?;
 
 	handle
 		.write_control(0x21, 0x09, 0x0304, 0x1, &main_packet, Duration::from_secs(5))
-		.unwrap();
+		.map_err(|e| Error::transfer("send main packet", e))?;
+	signal::check()?;
 	handle
 		.write_control(0x21, 0x09, 0x0304, 0x1, &buttons_packet, Duration::from_secs(5))
-		.unwrap();
+		.map_err(|e| Error::transfer("send buttons packet", e))?;
+	signal::check()?;
+	for packet in &macro_packets {
+		handle
+			.write_control(0x21, 0x09, 0x0304, 0x1, packet, Duration::from_secs(5))
+			.map_err(|e| Error::transfer("send macro packet", e))?;
+		signal::check()?;
+	}
 	handle
 		.write_control(0x21, 0x09, 0x0305, 0x1, &debounce_packet, Duration::from_secs(5))
-		.unwrap();
+		.map_err(|e| Error::transfer("send debounce packet", e))?;
+
+	Ok(())
+}
+
+/// Prints every operation [`write_config`] would perform for `config` on a
+/// `model`, with an annotated hexdump of each packet, without touching a
+/// device.
+///
+/// # Errors
+///
+/// If a DPI or button is not supported by the model (see
+/// [`Model::check_config`]), or see [`build_main_packet`]
+pub fn dry_run(config: &config::Config, model: &Model) -> Result<(), Error> {
+	model.check_config(config)?;
+
+	let mut mock = mock::MockDevice::new();
+	write_config(&mut mock, config, model)?;
+
+	println!("dry run for a {}", model.name);
+	for event in mock.events {
+		match event {
+			mock::MockEvent::Claim(interface) => println!("claim interface {interface}"),
+			mock::MockEvent::Release(interface) => println!("release interface {interface}"),
+			mock::MockEvent::Write(transfer) | mock::MockEvent::Read(transfer) => {
+				println!(
+					"control transfer: bmRequestType={:#04x} bRequest={:#04x} wValue={:#06x} \
+					 wIndex={:#06x} length={}",
+					transfer.request_type,
+					transfer.request,
+					transfer.value,
+					transfer.index,
+					transfer.data.len()
+				);
+				if let Some(packet_type) = layout::PacketType::identify(&transfer.data) {
+					println!("{}:", packet_type.name());
+				}
+				print!("{}", layout::annotated_hexdump(&transfer.data));
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Reads the configuration of the `model` behind `transport`. Settings not
+/// stored on the device are left at their defaults.
+///
+/// # Errors
+///
+/// If the interfaces cannot be claimed, a transfer fails, the program is
+/// interrupted (see [`signal::check`]), or the device returns a packet that
+/// cannot be decoded.
+pub fn read_config_from<T: Transport>(
+	transport: &mut T,
+	model: &Model,
+) -> Result<config::Config, Error> {
+	signal::check()?;
+	let mut handle = InterfaceScopeWrapper::wrap(transport, INTERFACES)?;
+
+	let main_packet = read_report::<_, 520>(&mut *handle, 0x11)?;
+	signal::check()?;
+	let buttons_packet = read_report::<_, 520>(&mut *handle, 0x12)?;
+	signal::check()?;
+
+	let mut debounce_packet = [0u8; 6];
+	handle
+		.read_control(0xa1, 0x01, 0x0305, 0x1, &mut debounce_packet, Duration::from_secs(5))
+		.map_err(|e| Error::transfer("read debounce packet", e))?;
+
+	Ok(decode::decode_packets(&main_packet, &buttons_packet, &debounce_packet, model)?)
+}
+
+/// Reattaches the kernel driver to every configuration interface of
+/// `device` which has none, e.g. after the program was killed while it had
+/// the interfaces claimed. Returns the interfaces that were reattached.
+///
+/// This uses the device handle directly rather than a [`Transport`], so it
+/// is not recorded by `--record`.
+///
+/// # Errors
+///
+/// If the device cannot be opened, or a kernel driver cannot be checked or
+/// reattached.
+pub fn recover(device: &Device) -> Result<Vec<u8>, Error> {
+	let mut handle = device::open(device)?;
+	let mut reattached = Vec::new();
+
+	for interface in INTERFACES {
+		let active = handle.kernel_driver_active(interface).map_err(|e| {
+			Error::usb(format!("check the kernel driver of interface {interface}"), e)
+		})?;
+
+		if !active {
+			handle.attach_kernel_driver(interface).map_err(|e| {
+				Error::usb(format!("reattach the kernel driver of interface {interface}"), e)
+			})?;
+			reattached.push(interface);
+		}
+	}
+
+	Ok(reattached)
+}
+
+/// Asks the device for the `0x0304` report identified by `command` (the
+/// second byte of the matching `Main Packet`) and reads it back. See the
+/// `Reading` section of `packet_spec.md`.
+fn read_report<T: Transport, const N: usize>(
+	transport: &mut T,
+	command: u8,
+) -> Result<[u8; N], Error> {
+	let mut data = [0u8; N];
+
+	transport
+		.write_control(
+			0x21,
+			0x09,
+			0x0305,
+			0x1,
+			&[0x05, command, 0x00, 0x00, 0x00, 0x00],
+			Duration::from_secs(5),
+		)
+		.and_then(|_| {
+			transport.read_control(0xa1, 0x01, 0x0304, 0x1, &mut data, Duration::from_secs(5))
+		})
+		.map_err(|e| Error::transfer(format!("read report {command:#04x}"), e))?;
+
+	Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		mock::{MockDevice, MockEvent},
+		*,
+	};
+	use crate::config::software::SoftwareAction;
+
+	#[test]
+	fn write_config_sends_all_packets() {
+		let model_o = &TARGET_DEVICES[1];
+		let config = config::Config::default();
+		let mut device = MockDevice::new();
+
+		write_config(&mut device, &config, model_o).unwrap();
+
+		assert_eq!(device.events[..2], [MockEvent::Claim(0), MockEvent::Claim(1)]);
+		assert_eq!(device.events[5..], [MockEvent::Release(0), MockEvent::Release(1)]);
+		assert!(device.claimed.is_empty());
+
+		let writes = device.writes();
+		assert_eq!(writes.len(), 3);
+		assert!(writes
+			.iter()
+			.all(|w| (w.request_type, w.request, w.index) == (0x21, 0x09, 0x1)));
+		assert_eq!(writes.iter().map(|w| w.value).collect::<Vec<_>>(), [0x0304, 0x0304, 0x0305]);
+		assert_eq!(writes[0].data, build_main_packet(&config).unwrap());
+		assert_eq!(writes[1].data, build_buttons_packet(&config, model_o).unwrap());
+		assert_eq!(writes[2].data, build_debounce_packet(&config));
+	}
+
+	#[test]
+	fn write_config_sends_macros() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut config = config::Config::default();
+		let macro_ = Macro::parse("repeat 2\nkey-down ctrl\ndelay 300\nkey-up ctrl\n").unwrap();
+		config.macros.insert(3, macro_);
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), ButtonAction::Macro(3));
+		let mut device = MockDevice::new();
+
+		write_config(&mut device, &config, model_o).unwrap();
+
+		let writes = device.writes();
+		assert_eq!(writes.len(), 4);
+		assert_eq!(writes[1].data[20..24], [0x70, 0x03, 0x00, 0x00]);
+		assert_eq!(writes[2].value, 0x0304);
+		assert_eq!(writes[2].data[..16], [
+			0x04, 0x13, 0x03, 0x01, 0x02, 0x02, 0x00, 0x00, // header
+			0x81, 0xe0, 0x01, 0x2c, // ctrl down, 300 ms
+			0x01, 0xe0, 0x00, 0x00, // ctrl up
+		]);
+		assert!(writes[2].data[16..].iter().all(|byte| *byte == 0));
+	}
+
+	#[test]
+	fn read_config_requests_and_decodes_reports() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut config = config::Config::default();
+		config.lighting.mode = config::lighting::Mode::Rave;
+		config
+			.buttons
+			.0
+			.insert("dpi".to_owned(), MouseButtonType::MiddleClick.into());
+		config.debounce_time = config::DebounceTime::_4ms;
+
+		let mut device = MockDevice::new();
+		device.respond(0x01, 0x0304, 0x1, &build_main_packet(&config).unwrap());
+		device.respond(0x01, 0x0304, 0x1, &build_buttons_packet(&config, model_o).unwrap());
+		device.respond(0x01, 0x0305, 0x1, &build_debounce_packet(&config));
+
+		let read = read_config_from(&mut device, model_o).unwrap();
+
+		let writes = device.writes();
+		assert_eq!(writes.len(), 2);
+		assert_eq!(writes[0].data, [0x05, 0x11, 0x00, 0x00, 0x00, 0x00]);
+		assert_eq!(writes[1].data, [0x05, 0x12, 0x00, 0x00, 0x00, 0x00]);
+		assert!(device.claimed.is_empty());
+
+		assert_eq!(build_main_packet(&read).unwrap(), build_main_packet(&config).unwrap());
+		assert_eq!(
+			build_buttons_packet(&read, model_o).unwrap(),
+			build_buttons_packet(&config, model_o).unwrap()
+		);
+		assert_eq!(build_debounce_packet(&read), build_debounce_packet(&config));
+	}
+
+	#[test]
+	fn checks_dpi_against_model() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut config = config::Config::default();
+		assert!(model_o.check_config(&config).is_ok());
+
+		config.dpi[2].x_dpi = 13000;
+		assert_eq!(
+			model_o.check_config(&config).unwrap_err().to_string(),
+			"dpi[2].x_dpi: 13 000 exceeds Model O maximum of 12 000"
+		);
+
+		config.dpi[2].x_dpi = 1250;
+		assert_eq!(
+			model_o.check_config(&config).unwrap_err().to_string(),
+			"dpi[2].x_dpi: 1 250 is not a multiple of Model O step of 100"
+		);
+
+		config.dpi[2].x_dpi = 25600;
+		assert!(matches!(build_main_packet(&config), Err(Error::Validation(_))));
+	}
+
+	#[test]
+	fn needs_experimental_for_unconfirmed_codes() {
+		let mut config = config::Config::default();
+		assert!(check_unconfirmed(&config, false).is_ok());
+
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), ButtonAction::Macro(3));
+		assert!(matches!(check_unconfirmed(&config, false), Err(Error::Unconfirmed(_))));
+		assert!(check_unconfirmed(&config, true).is_ok());
+
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), ButtonAction::try_from("key:ctrl+c").unwrap());
+		assert!(matches!(check_unconfirmed(&config, false), Err(Error::Unconfirmed(_))));
+
+		config.buttons.0.clear();
+		config
+			.macros
+			.insert(3, Macro::parse("key-down a\nkey-up a").unwrap());
+		assert!(matches!(check_unconfirmed(&config, false), Err(Error::Unconfirmed(_))));
+	}
+
+	#[test]
+	fn resolves_button_slots() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut config = config::Config::default();
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), MouseButtonType::ScrollUp.into());
+		config
+			.buttons
+			.0
+			.insert("slot-7".to_owned(), MouseButtonType::MediaMute.into());
+		config.software_buttons.insert(
+			"forward".to_owned(),
+			SoftwareAction::Command("notify-send forward".to_owned()),
+		);
+
+		let packet = build_buttons_packet(&config, model_o).unwrap();
+		let slot = |slot: usize| &packet[8 + slot * 4..12 + slot * 4];
+		assert_eq!(slot(0), [0x11, 0x01, 0x00, 0x00]);
+		assert_eq!(slot(3), [0x12, 0x01, 0x00, 0x00]);
+		assert_eq!(slot(4), [0x22, 0x00, 0x20, 0x00]);
+		assert_eq!(slot(5), [0x41, 0x00, 0x00, 0x00]);
+		assert_eq!(slot(6), [0x50, 0x01, 0x00, 0x00]);
+		assert_eq!(slot(7), [0x22, 0x10, 0x00, 0x00]);
+		assert!(model_o.check_config(&config).is_ok());
+
+		config
+			.buttons
+			.0
+			.insert("slot-3".to_owned(), MouseButtonType::Disable.into());
+		config
+			.buttons
+			.0
+			.insert("thumb".to_owned(), MouseButtonType::Disable.into());
+		config
+			.buttons
+			.0
+			.insert("slot-19".to_owned(), MouseButtonType::Disable.into());
+		let problems = model_o.validate(&config);
+		assert_eq!(
+			problems
+				.iter()
+				.map(|problem| problem.path.as_str())
+				.collect::<Vec<_>>(),
+			["buttons.slot-19", "buttons.slot-3", "buttons.thumb"]
+		);
+		assert_eq!(problems[1].message, "slot 3 is already set by `back`");
+		assert_eq!(
+			problems[2].message,
+			"the Model O has no button `thumb` (expected left, right, middle, back, forward, dpi, \
+			 or slot-0 to slot-18)"
+		);
+	}
+
+	#[test]
+	fn failed_claim_releases_claimed_interfaces() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut device = MockDevice::new();
+		// interface 1 is already in use
+		device.claimed.push(1);
+
+		let result = write_config(&mut device, &config::Config::default(), model_o);
+
+		assert!(matches!(result, Err(Error::Claim { interface: 1, .. })));
+		assert_eq!(device.events, [MockEvent::Claim(0), MockEvent::Release(0)]);
+		assert_eq!(device.claimed, [1]);
+	}
+
+	#[test]
+	fn failed_transfer_releases_interfaces() {
+		let model_o = &TARGET_DEVICES[1];
+		// no responses are queued, so the first read fails
+		let mut device = MockDevice::new();
+
+		let result = read_config_from(&mut device, model_o);
+
+		assert!(matches!(result, Err(Error::Transfer { .. })));
+		assert_eq!(device.events[device.events.len() - 2..], [
+			MockEvent::Release(0),
+			MockEvent::Release(1)
+		]);
+		assert!(device.claimed.is_empty());
+	}
 }
@@ -0,0 +1,521 @@
+use std::{
+	collections::HashMap,
+	fmt::{self, Formatter},
+};
+
+use serde_json::Value;
+
+use super::{
+	decode::{self, DecodeError},
+	fixture::Fixture,
+	layout::PacketType,
+	Model,
+	TARGET_DEVICES,
+};
+use crate::{
+	config::{self, Config},
+	error::Error,
+};
+
+/// `LINKTYPE_USB_LINUX`, usbmon with a 48 byte header
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// `LINKTYPE_USB_LINUX_MMAPPED`, usbmon with a 64 byte header
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+/// Reasons a capture file could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureError {
+	/// The file is neither a pcap nor a pcapng file.
+	UnknownFormat,
+	/// The file ended in the middle of a header, block or packet.
+	Truncated,
+	/// The capture does not contain usbmon packets.
+	UnsupportedLinkType(u32),
+}
+
+impl fmt::Display for CaptureError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::UnknownFormat => write!(f, "not a pcap or pcapng file"),
+			Self::Truncated => write!(f, "capture file is truncated"),
+			Self::UnsupportedLinkType(link_type) => write!(
+				f,
+				"link type {link_type} is not Linux usbmon ({LINKTYPE_USB_LINUX} or \
+				 {LINKTYPE_USB_LINUX_MMAPPED})"
+			),
+		}
+	}
+}
+
+impl std::error::Error for CaptureError {}
+
+/// A SET_REPORT transfer found in a capture.
+#[derive(Debug, Clone)]
+pub struct Report {
+	/// Seconds since the unix epoch, as recorded by usbmon
+	pub timestamp: f64,
+	pub bus: u16,
+	pub address: u8,
+	/// The model of the device, or [`None`] if the capture started after
+	/// the device was enumerated
+	pub model: Option<&'static Model>,
+	/// `wValue` of the transfer (`0x0304` or `0x0305`)
+	pub value: u16,
+	pub data: Vec<u8>,
+}
+
+/// Byte order of the capture file, which is also the byte order of the
+/// usbmon headers.
+#[derive(Debug, Clone, Copy)]
+enum Endian {
+	Little,
+	Big,
+}
+
+impl Endian {
+	fn u16(self, data: &[u8], offset: usize) -> Result<u16, CaptureError> {
+		let bytes = data
+			.get(offset..offset + 2)
+			.ok_or(CaptureError::Truncated)?
+			.try_into()
+			.unwrap();
+		Ok(match self {
+			Self::Little => u16::from_le_bytes(bytes),
+			Self::Big => u16::from_be_bytes(bytes),
+		})
+	}
+
+	fn u32(self, data: &[u8], offset: usize) -> Result<u32, CaptureError> {
+		let bytes = data
+			.get(offset..offset + 4)
+			.ok_or(CaptureError::Truncated)?
+			.try_into()
+			.unwrap();
+		Ok(match self {
+			Self::Little => u32::from_le_bytes(bytes),
+			Self::Big => u32::from_be_bytes(bytes),
+		})
+	}
+
+	fn u64(self, data: &[u8], offset: usize) -> Result<u64, CaptureError> {
+		let bytes = data
+			.get(offset..offset + 8)
+			.ok_or(CaptureError::Truncated)?
+			.try_into()
+			.unwrap();
+		Ok(match self {
+			Self::Little => u64::from_le_bytes(bytes),
+			Self::Big => u64::from_be_bytes(bytes),
+		})
+	}
+}
+
+/// A captured packet with the link type of its interface.
+struct Packet<'d> {
+	link_type: u32,
+	endian: Endian,
+	data: &'d [u8],
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], CaptureError> {
+	data.get(offset..offset + len)
+		.ok_or(CaptureError::Truncated)
+}
+
+/// Splits a pcap file into packets.
+fn pcap_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, CaptureError> {
+	let endian = match slice(data, 0, 4)? {
+		[0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => Endian::Little,
+		[0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => Endian::Big,
+		_ => return Err(CaptureError::UnknownFormat),
+	};
+	let link_type = endian.u32(data, 20)?;
+
+	let mut packets = Vec::new();
+	let mut offset = 24;
+	while offset < data.len() {
+		let captured_len = endian.u32(data, offset + 8)? as usize;
+		packets.push(Packet {
+			link_type,
+			endian,
+			data: slice(data, offset + 16, captured_len)?,
+		});
+		offset += 16 + captured_len;
+	}
+
+	Ok(packets)
+}
+
+/// Splits a pcapng file into packets. Only enhanced and simple packet
+/// blocks are read.
+fn pcapng_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, CaptureError> {
+	let mut packets = Vec::new();
+	let mut endian = Endian::Little;
+	let mut link_types = Vec::new();
+
+	let mut offset = 0;
+	while offset < data.len() {
+		let block_type = endian.u32(data, offset)?;
+
+		// section header blocks set the byte order of the following blocks
+		if block_type == 0x0a0d_0d0a {
+			endian = match slice(data, offset + 8, 4)? {
+				[0x4d, 0x3c, 0x2b, 0x1a] => Endian::Little,
+				[0x1a, 0x2b, 0x3c, 0x4d] => Endian::Big,
+				_ => return Err(CaptureError::UnknownFormat),
+			};
+			link_types.clear();
+		}
+
+		let block_len = endian.u32(data, offset + 4)? as usize;
+		let block = slice(data, offset, block_len)?;
+
+		match block_type {
+			// interface description block
+			0x0000_0001 => link_types.push(endian.u16(block, 8)? as u32),
+			// enhanced packet block
+			0x0000_0006 => {
+				let interface = endian.u32(block, 8)? as usize;
+				let captured_len = endian.u32(block, 20)? as usize;
+				packets.push(Packet {
+					link_type: *link_types.get(interface).ok_or(CaptureError::Truncated)?,
+					endian,
+					data: slice(block, 28, captured_len)?,
+				});
+			}
+			// simple packet block
+			0x0000_0003 => {
+				let original_len = endian.u32(block, 8)? as usize;
+				packets.push(Packet {
+					link_type: *link_types.first().ok_or(CaptureError::Truncated)?,
+					endian,
+					data: slice(block, 12, original_len.min(block_len.saturating_sub(16)))?,
+				});
+			}
+			_ => {}
+		}
+
+		offset += block_len.max(12);
+	}
+
+	Ok(packets)
+}
+
+/// Finds every SET_REPORT transfer of a `Main Packet` (`0x0304`) or
+/// `Debounce Packet` (`0x0305`) report in a pcap or pcapng usbmon capture.
+///
+/// Devices are identified by the device descriptors read while they were
+/// enumerated. Devices which are known not to be in [`TARGET_DEVICES`] are
+/// skipped, while devices which were enumerated before the capture started
+/// are included with no model.
+///
+/// # Errors
+///
+/// If the file cannot be parsed or does not contain usbmon packets.
+pub fn read_capture(data: &[u8]) -> Result<Vec<Report>, CaptureError> {
+	let packets = match slice(data, 0, 4)? {
+		[0x0a, 0x0d, 0x0d, 0x0a] => pcapng_packets(data)?,
+		_ => pcap_packets(data)?,
+	};
+
+	let mut models = HashMap::<(u16, u8), Option<&'static Model>>::new();
+	let mut reports = Vec::new();
+
+	for Packet {
+		link_type,
+		endian,
+		data,
+	} in packets
+	{
+		let header_len = match link_type {
+			LINKTYPE_USB_LINUX => 48,
+			LINKTYPE_USB_LINUX_MMAPPED => 64,
+			_ => return Err(CaptureError::UnsupportedLinkType(link_type)),
+		};
+
+		// usbmon header, see `struct mon_bin_hdr` in the kernel
+		let header = slice(data, 0, 40)?;
+		let (event_type, transfer_type, endpoint, address) =
+			(header[8], header[9], header[10], header[11]);
+		let bus = endian.u16(header, 12)?;
+		let setup_present = header[14] == 0;
+		let timestamp =
+			endian.u64(header, 16)? as f64 + endian.u32(header, 24)? as f64 / 1_000_000.0;
+		let captured_len = endian.u32(header, 36)? as usize;
+		let payload = data
+			.get(header_len..header_len + captured_len)
+			.ok_or(CaptureError::Truncated)?;
+
+		// only control transfers are interesting
+		if transfer_type != 2 {
+			continue
+		}
+
+		match event_type {
+			// a completed GET_DESCRIPTOR(device) has the ids at offset 8
+			b'C' if endpoint & 0x80 != 0 && payload.len() >= 12 && payload[..2] == [18, 0x01] => {
+				let id = (
+					u16::from_le_bytes([payload[8], payload[9]]),
+					u16::from_le_bytes([payload[10], payload[11]]),
+				);
+				models.insert(
+					(bus, address),
+					TARGET_DEVICES
+						.iter()
+						.find(|model| (model.vendor_id, model.product_id) == id),
+				);
+			}
+			b'S' if setup_present => {
+				let setup = slice(data, 40, 8)?;
+				let value = u16::from_le_bytes([setup[2], setup[3]]);
+
+				if setup[0] != 0x21 || setup[1] != 0x09 || !matches!(value, 0x0304 | 0x0305) {
+					continue
+				}
+
+				let model = match models.get(&(bus, address)) {
+					Some(None) => continue,
+					Some(Some(model)) => Some(*model),
+					None => None,
+				};
+
+				reports.push(Report {
+					timestamp,
+					bus,
+					address,
+					model,
+					value,
+					data: payload.to_vec(),
+				});
+			}
+			_ => {}
+		}
+	}
+
+	Ok(reports)
+}
+
+/// A field changed by a report as `(<dotted path>, <old value>, <new value>)`
+pub type Change = (String, Value, Value);
+
+/// What the reports of a capture showed about one device.
+#[derive(Debug, Default)]
+struct CapturedDevice {
+	config: Config,
+	model: Option<&'static Model>,
+	/// Last report of each type
+	reports: HashMap<PacketType, Vec<u8>>,
+}
+
+/// Decodes the reports of a capture in order, tracking the config of every
+/// device in it. Each device starts from the default config.
+#[derive(Debug, Default)]
+pub struct CaptureDecoder {
+	devices: HashMap<(u16, u8), CapturedDevice>,
+}
+
+impl CaptureDecoder {
+	/// Decodes `report` into the config of its device. Returns the fields
+	/// the report changed (see [`config::json_diff`]), or [`None`] if it is
+	/// not a known packet.
+	///
+	/// # Errors
+	///
+	/// If the report is a known packet which cannot be decoded. Fields
+	/// decoded before the failure are kept.
+	pub fn decode(&mut self, report: &Report) -> Result<Option<Vec<Change>>, DecodeError> {
+		let device = self
+			.devices
+			.entry((report.bus, report.address))
+			.or_default();
+		device.model = device.model.or(report.model);
+
+		let packet_type = match PacketType::identify(&report.data) {
+			Some(packet_type) => packet_type,
+			None => return Ok(None),
+		};
+
+		// decoded values are always in range, so the configs serialize
+		let old_json = serde_json::to_value(&device.config).unwrap();
+
+		// `identify` checked the length of the report
+		let data = report.data.as_slice();
+		let config = &mut device.config;
+		match packet_type {
+			PacketType::Main => decode::decode_main_packet(data.try_into().unwrap(), config),
+			PacketType::Buttons =>
+				decode::decode_buttons_packet(data.try_into().unwrap(), config, device.model),
+			PacketType::Debounce =>
+				decode::decode_debounce_packet(data.try_into().unwrap(), config),
+			PacketType::Macro => decode::decode_macro_packet(data.try_into().unwrap(), config),
+		}?;
+
+		let changes = config::json_diff(&old_json, &serde_json::to_value(&*config).unwrap());
+		device.reports.insert(packet_type, report.data.clone());
+
+		Ok(Some(changes))
+	}
+
+	/// Config of the device at `bus` and `address` after the reports decoded
+	/// so far.
+	pub fn config(&self, bus: u16, address: u8) -> Option<&Config> {
+		self.devices
+			.get(&(bus, address))
+			.map(|device| &device.config)
+	}
+
+	/// Creates a golden test fixture from the last report of each type sent
+	/// to the only device in the capture.
+	///
+	/// # Errors
+	///
+	/// If the capture does not contain exactly one device, its model is
+	/// unknown, it was not sent a report of each type, or the reports do not
+	/// decode into a valid config.
+	pub fn fixture(&self) -> Result<Fixture, Error> {
+		let device = match self.devices.values().collect::<Vec<_>>()[..] {
+			[device] => device,
+			_ =>
+				return Err(Error::Usage(
+					"a fixture can only be created from a capture of exactly one mouse".to_owned(),
+				)),
+		};
+
+		let packet = |packet_type: PacketType| {
+			device
+				.reports
+				.get(&packet_type)
+				.map(Vec::as_slice)
+				.ok_or_else(|| {
+					Error::Usage(format!("the capture does not contain a {}", packet_type.name()))
+				})
+		};
+		let model = device.model.ok_or_else(|| {
+			Error::Usage(
+				"the model of the mouse is unknown, so its buttons cannot be named (start the \
+				 capture before connecting the mouse)"
+					.to_owned(),
+			)
+		})?;
+
+		// reports are only kept if `identify` accepted their length
+		let main_packet = packet(PacketType::Main)?.try_into().unwrap();
+		let buttons_packet = packet(PacketType::Buttons)?.try_into().unwrap();
+		let debounce_packet = packet(PacketType::Debounce)?.try_into().unwrap();
+
+		let mut config =
+			decode::decode_packets(&main_packet, &buttons_packet, &debounce_packet, model)?;
+		config.check()?;
+
+		Ok(Fixture {
+			config,
+			main_packet,
+			buttons_packet,
+			debounce_packet,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Builds a little endian usbmon packet (48 byte header).
+	fn usbmon_packet(
+		event_type: u8,
+		endpoint: u8,
+		setup: Option<[u8; 8]>,
+		payload: &[u8],
+	) -> Vec<u8> {
+		let mut packet = vec![0u8; 48];
+		packet[8] = event_type;
+		packet[9] = 2;
+		packet[10] = endpoint;
+		packet[11] = 5;
+		packet[12..14].copy_from_slice(&3u16.to_le_bytes());
+		packet[14] = match setup {
+			Some(_) => 0,
+			None => b'-',
+		};
+		packet[16..24].copy_from_slice(&10u64.to_le_bytes());
+		packet[36..40].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+		if let Some(setup) = setup {
+			packet[40..48].copy_from_slice(&setup);
+		}
+		packet.extend_from_slice(payload);
+		packet
+	}
+
+	fn pcap(packets: &[Vec<u8>]) -> Vec<u8> {
+		let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+		file.extend_from_slice(&[0; 8]);
+		file.extend_from_slice(&65535u32.to_le_bytes());
+		file.extend_from_slice(&LINKTYPE_USB_LINUX.to_le_bytes());
+
+		for packet in packets {
+			file.extend_from_slice(&[0; 8]);
+			file.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+			file.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+			file.extend_from_slice(packet);
+		}
+		file
+	}
+
+	#[test]
+	fn finds_reports_of_enumerated_devices() {
+		let mut descriptor = [0u8; 18];
+		descriptor[..2].copy_from_slice(&[18, 0x01]);
+		descriptor[8..10].copy_from_slice(&0x258au16.to_le_bytes());
+		descriptor[10..12].copy_from_slice(&0x0036u16.to_le_bytes());
+
+		let debounce = [0x05, 0x1a, 0x03, 0x00, 0x00, 0x00];
+
+		let capture = pcap(&[
+			usbmon_packet(b'C', 0x80, None, &descriptor),
+			// GET_REPORT, ignored
+			usbmon_packet(b'S', 0x80, Some([0xa1, 0x01, 0x05, 0x03, 1, 0, 6, 0]), &[]),
+			usbmon_packet(b'S', 0x00, Some([0x21, 0x09, 0x05, 0x03, 1, 0, 6, 0]), &debounce),
+		]);
+
+		let reports = read_capture(&capture).unwrap();
+		assert_eq!(reports.len(), 1);
+		assert_eq!(reports[0].model.map(|model| model.key), Some("model-o"));
+		assert_eq!((reports[0].bus, reports[0].address), (3, 5));
+		assert_eq!(reports[0].value, 0x0305);
+		assert_eq!(reports[0].data, debounce);
+		assert_eq!(reports[0].timestamp, 10.0);
+
+		assert!(matches!(read_capture(b"nope"), Err(CaptureError::UnknownFormat)));
+	}
+
+	#[test]
+	fn decodes_reports_per_device() {
+		let report = |address, data: &[u8]| Report {
+			timestamp: 0.0,
+			bus: 1,
+			address,
+			model: Some(&TARGET_DEVICES[1]),
+			value: 0x0305,
+			data: data.to_vec(),
+		};
+		let mut decoder = CaptureDecoder::default();
+
+		let changes = decoder
+			.decode(&report(2, &[0x05, 0x1a, 0x08, 0x00, 0x00, 0x00]))
+			.unwrap()
+			.unwrap();
+		assert_eq!(changes.len(), 1);
+		assert_eq!(changes[0].0, "debounce_time");
+		assert_eq!(decoder.decode(&report(2, &[0x05, 0x1a])).unwrap(), None);
+		assert!(decoder
+			.decode(&report(3, &[0x05, 0x1a, 0x7f, 0x00, 0x00, 0x00]))
+			.is_err());
+
+		assert!(decoder.config(1, 2).is_some());
+		assert!(decoder.config(2, 1).is_none());
+		assert_eq!(
+			decoder.fixture().unwrap_err().to_string(),
+			"a fixture can only be created from a capture of exactly one mouse"
+		);
+	}
+}
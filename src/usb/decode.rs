@@ -0,0 +1,679 @@
+use std::{
+	error::Error,
+	fmt::{self, Formatter},
+};
+
+use super::{slot_name, Model, BUTTON_SLOTS, DPI_UNIT};
+use crate::config::{
+	self,
+	buttons::ButtonAction,
+	from_discriminant,
+	lighting,
+	macros::{Macro, MacroButton, MacroEvent, MacroKey, MacroRepeat, MacroStep, MACRO_EVENTS},
+	Color,
+	DebounceTime,
+	Dpi,
+	LiftoffDistance,
+	PollingRate,
+	RangedByte,
+};
+
+/// Reasons a packet could not be decoded into a [`config::Config`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+	/// The packet does not start with the report id and command expected for
+	/// its type.
+	WrongReport {
+		expected: [u8; 2],
+		found: [u8; 2],
+	},
+	/// The XY independent / polling rate byte has unknown bits set, or the
+	/// polling rate is not a known [`PollingRate`].
+	InvalidPollingRate(u8),
+	/// The DPI enable bitset has bits set outside of the 6 DPI slots,
+	/// enables no DPIs, or disagrees with the enabled DPI count.
+	InvalidDpiEnableFlags {
+		flags: u8,
+		count: u8,
+	},
+	/// The selected DPI does not refer to an enabled DPI.
+	InvalidSelectedDpi {
+		selected: u8,
+		count: u8,
+	},
+	/// The packet claims XY independent DPIs are off but contains data in
+	/// the second half of the DPI block.
+	UnexpectedYDpi,
+	UnknownLedMode(u8),
+	UnknownRainbowDirection(u8),
+	UnknownLiftoffDistance(u8),
+	UnknownDebounceTime(u8),
+	UnknownButtonType {
+		button: String,
+		code: u32,
+	},
+	UnknownMacroRepeat(u8),
+	/// Event `index` (counting from 1) of a macro has an unknown type, or
+	/// presses an unknown mouse button.
+	UnknownMacroEvent {
+		index: usize,
+		code: [u8; 2],
+	},
+	/// The event count of a macro is larger than fits in the packet.
+	TooManyMacroEvents(u8),
+	/// A byte with a fixed nibble (the `4` above a speed, or the `0` below a
+	/// brightness) has something else there.
+	InvalidFixedNibble {
+		field: &'static str,
+		byte: u8,
+	},
+	/// DPI `index` (counting from 1) is 0.
+	ZeroDpi(usize),
+	/// A value (after removing any fixed nibble) is outside of the range the
+	/// config allows.
+	OutOfRange {
+		field: &'static str,
+		value: u8,
+		min: u8,
+		max: u8,
+	},
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::WrongReport { expected, found } => write!(
+				f,
+				"expected report starting with {:02x} {:02x}, found {:02x} {:02x}",
+				expected[0], expected[1], found[0], found[1]
+			),
+			Self::InvalidPollingRate(byte) =>
+				write!(f, "invalid XY independent / polling rate byte {byte:#04x}"),
+			Self::InvalidDpiEnableFlags { flags, count } => write!(
+				f,
+				"DPI enable bitset {flags:#010b} does not match enabled DPI count {count}"
+			),
+			Self::InvalidSelectedDpi { selected, count } =>
+				write!(f, "selected DPI {selected} is not one of the {count} enabled DPIs"),
+			Self::UnexpectedYDpi =>
+				write!(f, "Y DPI values present while XY independent DPI is off"),
+			Self::UnknownLedMode(mode) => write!(f, "unknown LED mode {mode:#04x}"),
+			Self::UnknownRainbowDirection(direction) =>
+				write!(f, "unknown rainbow direction {direction:#04x}"),
+			Self::UnknownLiftoffDistance(distance) =>
+				write!(f, "unknown liftoff distance {distance:#04x}"),
+			Self::UnknownDebounceTime(time) => write!(f, "unknown debounce time {time:#04x}"),
+			Self::UnknownButtonType { button, code } =>
+				write!(f, "unknown action {code:#010x} for {button} button"),
+			Self::UnknownMacroRepeat(mode) => write!(f, "unknown macro repeat mode {mode:#04x}"),
+			Self::UnknownMacroEvent { index, code } =>
+				write!(f, "unknown macro event {:02x} {:02x} (event {index})", code[0], code[1]),
+			Self::TooManyMacroEvents(count) =>
+				write!(f, "macro event count {count} is more than fits in the packet"),
+			Self::InvalidFixedNibble { field, byte } =>
+				write!(f, "{field} byte {byte:#04x} does not have the expected fixed nibble"),
+			Self::ZeroDpi(index) => write!(f, "DPI {index} is 0"),
+			Self::OutOfRange {
+				field,
+				value,
+				min,
+				max,
+			} => write!(f, "{field} {value} was not in range {min}..{max}"),
+		}
+	}
+}
+
+impl Error for DecodeError {}
+
+/// Sequential reader over a packet, mirroring the cursor used by the packet
+/// builders.
+struct PacketReader<'p> {
+	data: &'p [u8],
+	position: usize,
+}
+
+impl<'p> PacketReader<'p> {
+	fn new(data: &'p [u8]) -> Self {
+		Self { data, position: 0 }
+	}
+
+	fn byte(&mut self) -> u8 {
+		self.position += 1;
+		self.data[self.position - 1]
+	}
+
+	fn bytes<const N: usize>(&mut self) -> [u8; N] {
+		let mut bytes = [0u8; N];
+		bytes.copy_from_slice(&self.data[self.position..self.position + N]);
+		self.position += N;
+		bytes
+	}
+
+	fn skip(&mut self, count: usize) {
+		self.position += count;
+	}
+
+	/// Checks that the packet starts with the report id and command in
+	/// `expected`
+	fn header(&mut self, expected: [u8; 2]) -> Result<(), DecodeError> {
+		let found = self.bytes();
+
+		match found == expected {
+			true => Ok(()),
+			false => Err(DecodeError::WrongReport { expected, found }),
+		}
+	}
+
+	/// Reads a color stored as `RGB`
+	fn color_rgb(&mut self) -> Color {
+		let [r, g, b] = self.bytes();
+		Color { r, g, b }
+	}
+
+	/// Reads a color stored as `RBG`
+	fn color_rbg(&mut self) -> Color {
+		let [r, b, g] = self.bytes();
+		Color { r, g, b }
+	}
+
+	/// Reads a DPI stored in hundreds, naming it DPI `index` if it is 0
+	fn dpi(&mut self, index: usize) -> Result<u16, DecodeError> {
+		match self.byte() {
+			0 => Err(DecodeError::ZeroDpi(index)),
+			dpi => Ok(dpi as u16 * DPI_UNIT),
+		}
+	}
+
+	/// Reads a brightness stored in the high nibble of a byte with a fixed
+	/// low nibble of `0`
+	fn brightness(&mut self, field: &'static str) -> Result<RangedByte<1, 4>, DecodeError> {
+		let byte = self.byte();
+		match byte & 0x0f {
+			0 => ranged(field, byte >> 4),
+			_ => Err(DecodeError::InvalidFixedNibble { field, byte }),
+		}
+	}
+
+	/// Reads a combined brightness / speed byte
+	fn brightness_speed(
+		&mut self,
+		brightness_field: &'static str,
+		speed_field: &'static str,
+	) -> Result<(RangedByte<1, 4>, RangedByte<1, 3>), DecodeError> {
+		let byte = self.byte();
+		Ok((ranged(brightness_field, byte >> 4)?, ranged(speed_field, byte & 0x0f)?))
+	}
+
+	/// Reads a speed stored in the low nibble of a byte with a fixed high
+	/// nibble of `4`
+	fn prefixed_speed(&mut self, field: &'static str) -> Result<RangedByte<1, 3>, DecodeError> {
+		let byte = self.byte();
+		match byte >> 4 {
+			4 => ranged(field, byte & 0x0f),
+			_ => Err(DecodeError::InvalidFixedNibble { field, byte }),
+		}
+	}
+}
+
+/// Converts `value` into a [`RangedByte`], naming it `field` if it is out of
+/// range.
+fn ranged<const MIN: u8, const MAX: u8>(
+	field: &'static str,
+	value: u8,
+) -> Result<RangedByte<MIN, MAX>, DecodeError> {
+	match (MIN..=MAX).contains(&value) {
+		true => Ok(RangedByte(value)),
+		false => Err(DecodeError::OutOfRange {
+			field,
+			value,
+			min: MIN,
+			max: MAX,
+		}),
+	}
+}
+
+/// Decodes a packet matching the `Type 1` section of the `Main Packet`
+/// section of `packet_spec.md` into `config`, replacing DPI, polling rate,
+/// liftoff distance, lighting settings and raw data.
+///
+/// # Errors
+///
+/// If the packet is not a `Type 1` packet, or contains a value which cannot
+/// be represented in [`config::Config`].
+pub fn decode_main_packet(
+	packet: &[u8; 520],
+	config: &mut config::Config,
+) -> Result<(), DecodeError> {
+	let mut data = PacketReader::new(packet);
+
+	let raw = &mut config.raw;
+
+	data.header([0x04, 0x11])?;
+	raw.header.0 = data.bytes();
+
+	let xy_polling = data.byte();
+	let separate_xy_dpi = xy_polling & 0x08 != 0;
+	config.polling_rate = from_discriminant(xy_polling & !0x08, |r: PollingRate| r as u8)
+		.ok_or(DecodeError::InvalidPollingRate(xy_polling))?;
+
+	// dpi enable flags
+	let selected_count = data.byte();
+	let (selected, count) = (selected_count >> 4, selected_count & 0x0f);
+	let flags = !data.byte();
+
+	if flags & 0b1100_0000 != 0 || flags == 0 || flags.count_ones() != count as u32 {
+		return Err(DecodeError::InvalidDpiEnableFlags {
+			flags: !flags,
+			count,
+		})
+	}
+
+	if !(1..=count).contains(&selected) {
+		return Err(DecodeError::InvalidSelectedDpi { selected, count })
+	}
+
+	let mut enabled_count = 0u8;
+	for (i, dpi) in config.dpi.iter_mut().enumerate() {
+		dpi.enable = flags & (0b1 << i) != 0;
+		if dpi.enable {
+			enabled_count += 1;
+			if enabled_count == selected {
+				config.current_dpi = RangedByte(i as u8);
+			}
+		}
+	}
+
+	for (i, Dpi { x_dpi, y_dpi, .. }) in config.dpi.iter_mut().enumerate() {
+		*x_dpi = data.dpi(i + 1)?;
+		*y_dpi = match separate_xy_dpi {
+			true => data.dpi(i + 1)?,
+			false => *x_dpi,
+		};
+	}
+
+	if !separate_xy_dpi && data.bytes::<6>() != [0x00; 6] {
+		return Err(DecodeError::UnexpectedYDpi)
+	}
+
+	// unknown data
+	raw.after_dpi.0 = data.bytes();
+
+	for dpi in &mut config.dpi {
+		dpi.color = data.color_rgb();
+	}
+
+	// unknown data
+	config.raw.after_dpi_colors.0 = data.bytes();
+
+	let lighting = &mut config.lighting;
+
+	let mode = data.byte();
+	lighting.mode = from_discriminant(mode, |m: lighting::Mode| m as u8)
+		.ok_or(DecodeError::UnknownLedMode(mode))?;
+
+	lighting.rainbow.speed = data.prefixed_speed("rainbow speed")?;
+	let direction = data.byte();
+	lighting.rainbow.direction =
+		from_discriminant(direction, |d: lighting::RainbowDirection| d as u8)
+			.ok_or(DecodeError::UnknownRainbowDirection(direction))?;
+
+	lighting.solid.brightness = data.brightness("solid brightness")?;
+	lighting.solid.color = data.color_rbg();
+
+	// unknown data
+	config.raw.before_breathing.0 = data.bytes();
+
+	for color in &mut lighting.breathing.colors {
+		*color = data.color_rbg();
+	}
+
+	(lighting.tail.brightness, lighting.tail.speed) =
+		data.brightness_speed("tail brightness", "tail speed")?;
+	lighting.fade.speed = data.prefixed_speed("fade speed")?;
+
+	// unknown data
+	config.raw.after_fade.0 = data.bytes();
+
+	(lighting.rave.brightness, lighting.rave.speed) =
+		data.brightness_speed("rave brightness", "rave speed")?;
+	for color in &mut lighting.rave.colors {
+		*color = data.color_rbg();
+	}
+
+	// unknown data
+	config.raw.before_wave.0 = data.bytes();
+
+	(lighting.wave.brightness, lighting.wave.speed) =
+		data.brightness_speed("wave brightness", "wave speed")?;
+	lighting.breathing_single.speed = ranged("breathing (single) speed", data.byte())?;
+	lighting.breathing_single.color = data.color_rbg();
+
+	let liftoff_distance = data.byte();
+	config.liftoff_distance = from_discriminant(liftoff_distance, |d: LiftoffDistance| d as u8)
+		.ok_or(DecodeError::UnknownLiftoffDistance(liftoff_distance))?;
+
+	Ok(())
+}
+
+/// Decodes a packet matching the `Type 2` section of the `Main Packet`
+/// section of `packet_spec.md` into `config`, replacing button actions.
+/// Slots are named after the buttons of `model`, and only kept in the config
+/// if they differ from the model's default. Without a model, every slot
+/// which is not disabled is kept as `slot-<n>`.
+///
+/// # Errors
+///
+/// If the packet is not a `Type 2` packet, or contains an unknown button
+/// action.
+pub fn decode_buttons_packet(
+	packet: &[u8; 520],
+	config: &mut config::Config,
+	model: Option<&Model>,
+) -> Result<(), DecodeError> {
+	let mut data = PacketReader::new(packet);
+
+	data.header([0x04, 0x12])?;
+
+	// unknown data
+	data.skip(6);
+
+	let buttons = &mut config.buttons.0;
+	buttons.clear();
+	for slot in 0..BUTTON_SLOTS {
+		let (name, default) = match model {
+			Some(model) => (model.button_name(slot), model.default_button(slot)),
+			None => (slot_name(slot), config::MouseButtonType::Disable.into()),
+		};
+
+		let code = data.bytes();
+		let action =
+			ButtonAction::from_code(code).ok_or_else(|| DecodeError::UnknownButtonType {
+				button: name.clone(),
+				code: u32::from_be_bytes(code),
+			})?;
+
+		if action != default {
+			buttons.insert(name, action);
+		}
+	}
+
+	Ok(())
+}
+
+/// Decodes a packet matching the `Debounce Packet` section of
+/// `packet_spec.md` into `config`, replacing the debounce time.
+///
+/// # Errors
+///
+/// If the packet is not a `Debounce Packet`, or contains an unknown
+/// debounce time.
+pub fn decode_debounce_packet(
+	packet: &[u8; 6],
+	config: &mut config::Config,
+) -> Result<(), DecodeError> {
+	let mut data = PacketReader::new(packet);
+
+	data.header([0x05, 0x1a])?;
+
+	let time = data.byte();
+	config.debounce_time = from_discriminant(time, |t: DebounceTime| t as u8)
+		.ok_or(DecodeError::UnknownDebounceTime(time))?;
+
+	Ok(())
+}
+
+/// Decodes a packet matching the `Type 3` section of the `Main Packet`
+/// section of `packet_spec.md` into `config`, replacing the macro in the
+/// slot it stores.
+///
+/// # Errors
+///
+/// If the packet is not a `Type 3` packet, or contains an unknown repeat
+/// mode or event.
+pub fn decode_macro_packet(
+	packet: &[u8; 520],
+	config: &mut config::Config,
+) -> Result<(), DecodeError> {
+	let mut data = PacketReader::new(packet);
+
+	data.header([0x04, 0x13])?;
+
+	let slot = data.byte();
+	let repeat = match data.bytes() {
+		[0x01, times] => MacroRepeat::Times(times),
+		[0x02, _] => MacroRepeat::WhileHeld,
+		[0x03, _] => MacroRepeat::Toggle,
+		[mode, _] => return Err(DecodeError::UnknownMacroRepeat(mode)),
+	};
+	let event_count = data.byte();
+	if event_count as usize > MACRO_EVENTS {
+		return Err(DecodeError::TooManyMacroEvents(event_count))
+	}
+
+	// padding
+	data.skip(2);
+
+	let mut events = Vec::new();
+	for index in 1..=event_count as usize {
+		let code = data.bytes();
+		let button = || {
+			from_discriminant(code[1], |b: MacroButton| b as u8)
+				.ok_or(DecodeError::UnknownMacroEvent { index, code })
+		};
+		let step = match code {
+			[0x81, key] => MacroStep::KeyDown(MacroKey(key)),
+			[0x01, key] => MacroStep::KeyUp(MacroKey(key)),
+			[0x82, _] => MacroStep::ButtonDown(button()?),
+			[0x02, _] => MacroStep::ButtonUp(button()?),
+			_ => return Err(DecodeError::UnknownMacroEvent { index, code }),
+		};
+		events.push(step.into());
+
+		let delay = u16::from_be_bytes(data.bytes());
+		if delay != 0 {
+			events.push(MacroEvent::Delay(delay));
+		}
+	}
+
+	config.macros.insert(slot, Macro { repeat, events });
+
+	Ok(())
+}
+
+/// Decodes a full set of packets read from a `model` into a new
+/// [`config::Config`]. Settings which are not stored in any packet are left
+/// at their defaults.
+///
+/// # Errors
+///
+/// See [`decode_main_packet`], [`decode_buttons_packet`] and
+/// [`decode_debounce_packet`]
+pub fn decode_packets(
+	main_packet: &[u8; 520],
+	buttons_packet: &[u8; 520],
+	debounce_packet: &[u8; 6],
+	model: &Model,
+) -> Result<config::Config, DecodeError> {
+	let mut config = config::Config::default();
+
+	decode_main_packet(main_packet, &mut config)?;
+	decode_buttons_packet(buttons_packet, &mut config, Some(model))?;
+	decode_debounce_packet(debounce_packet, &mut config)?;
+
+	Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		config::MouseButtonType,
+		usb::{
+			build_buttons_packet,
+			build_debounce_packet,
+			build_macro_packet,
+			build_main_packet,
+			TARGET_DEVICES,
+		},
+	};
+
+	#[test]
+	fn round_trips_through_builders() {
+		let model_o = &TARGET_DEVICES[1];
+		let mut config = config::Config {
+			polling_rate: PollingRate::_500hz,
+			current_dpi: RangedByte(4),
+			liftoff_distance: LiftoffDistance::_3mm,
+			debounce_time: DebounceTime::_16ms,
+			..Default::default()
+		};
+		config.dpi[4].enable = true;
+		config.dpi[0].enable = false;
+		config.dpi[2].y_dpi = 2000;
+		config.dpi[3].color = Color { r: 1, g: 2, b: 3 };
+		config.lighting.mode = lighting::Mode::BreathingSingle;
+		config.lighting.breathing.colors[3] = Color { r: 4, g: 5, b: 6 };
+		config.lighting.rave.speed = RangedByte(3);
+		config
+			.buttons
+			.0
+			.insert("back".to_owned(), MouseButtonType::ScrollDown.into());
+		config
+			.buttons
+			.0
+			.insert("forward".to_owned(), ButtonAction::try_from("key:ctrl+shift+t").unwrap());
+		config
+			.buttons
+			.0
+			.insert("slot-12".to_owned(), MouseButtonType::VolumeUp.into());
+		config.raw.after_fade.0[3] = 0x12;
+		config.raw.header.0[1] = 0x7c;
+
+		let (main, buttons, debounce) = (
+			build_main_packet(&config).unwrap(),
+			build_buttons_packet(&config, model_o).unwrap(),
+			build_debounce_packet(&config),
+		);
+		let decoded = decode_packets(&main, &buttons, &debounce, model_o).unwrap();
+
+		assert_eq!(build_main_packet(&decoded).unwrap(), main);
+		assert_eq!(build_buttons_packet(&decoded, model_o).unwrap(), buttons);
+		// buttons which keep their default action are left out
+		assert_eq!(decoded.buttons.0.keys().collect::<Vec<_>>(), ["back", "forward", "slot-12"]);
+		assert_eq!(build_debounce_packet(&decoded), debounce);
+	}
+
+	#[test]
+	fn round_trips_macros() {
+		let macro_ = Macro {
+			repeat: MacroRepeat::Toggle,
+			events: vec![
+				MacroEvent::ButtonDown(MacroButton::Forward),
+				MacroEvent::Delay(15),
+				MacroEvent::ButtonUp(MacroButton::Forward),
+				MacroEvent::KeyDown(MacroKey(0x2c)),
+				MacroEvent::KeyUp(MacroKey(0x2c)),
+			],
+		};
+		let packet = build_macro_packet(5, &macro_).unwrap();
+		let mut config = config::Config::default();
+
+		decode_macro_packet(&packet, &mut config).unwrap();
+		assert_eq!(config.macros[&5], macro_);
+
+		let mut packet = packet;
+		packet[12] = 0x83;
+		assert_eq!(
+			decode_macro_packet(&packet, &mut config),
+			Err(DecodeError::UnknownMacroEvent {
+				index: 2,
+				code: [0x83, 0x10],
+			})
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_fields() {
+		let model_o = &TARGET_DEVICES[1];
+		let config = config::Config::default();
+		let decode_main = |offset: usize, value: u8| {
+			let mut packet = build_main_packet(&config).unwrap();
+			packet[offset] = value;
+			decode_main_packet(&packet, &mut config::Config::default())
+		};
+
+		assert_eq!(
+			decode_main(1, 0x12),
+			Err(DecodeError::WrongReport {
+				expected: [0x04, 0x11],
+				found: [0x04, 0x12],
+			})
+		);
+		assert_eq!(decode_main(10, 0x07), Err(DecodeError::InvalidPollingRate(0x07)));
+		assert_eq!(decode_main(10, 0x84), Err(DecodeError::InvalidPollingRate(0x84)));
+		assert_eq!(
+			decode_main(11, 0x14),
+			Err(DecodeError::InvalidDpiEnableFlags {
+				flags: 0b1111_1000,
+				count: 4,
+			})
+		);
+		assert_eq!(
+			decode_main(11, 0x43),
+			Err(DecodeError::InvalidSelectedDpi {
+				selected: 4,
+				count: 3,
+			})
+		);
+		assert_eq!(
+			decode_main(12, 0b0111_1000),
+			Err(DecodeError::InvalidDpiEnableFlags {
+				flags: 0b0111_1000,
+				count: 3,
+			})
+		);
+		assert_eq!(decode_main(15, 0x00), Err(DecodeError::ZeroDpi(3)));
+		assert_eq!(decode_main(53, 0x0b), Err(DecodeError::UnknownLedMode(0x0b)));
+		assert_eq!(
+			decode_main(54, 0x32),
+			Err(DecodeError::InvalidFixedNibble {
+				field: "rainbow speed",
+				byte: 0x32,
+			})
+		);
+		assert_eq!(
+			decode_main(56, 0x41),
+			Err(DecodeError::InvalidFixedNibble {
+				field: "solid brightness",
+				byte: 0x41,
+			})
+		);
+		assert_eq!(
+			decode_main(83, 0x51),
+			Err(DecodeError::OutOfRange {
+				field: "tail brightness",
+				value: 5,
+				min: 1,
+				max: 4,
+			})
+		);
+
+		let mut buttons = build_buttons_packet(&config, model_o).unwrap();
+		buttons[8..12].copy_from_slice(&[0x13, 0x00, 0x00, 0x00]);
+		assert_eq!(
+			decode_buttons_packet(&buttons, &mut config::Config::default(), Some(model_o)),
+			Err(DecodeError::UnknownButtonType {
+				button: "left".to_owned(),
+				code: 0x13000000,
+			})
+		);
+
+		assert_eq!(
+			decode_debounce_packet(
+				&[0x05, 0x1a, 0x09, 0x00, 0x00, 0x00],
+				&mut config::Config::default()
+			),
+			Err(DecodeError::UnknownDebounceTime(0x09))
+		);
+	}
+}
@@ -0,0 +1,281 @@
+use std::{
+	fmt::{self, Formatter},
+	str::FromStr,
+};
+
+use rusb::GlobalContext;
+
+use super::{record::RecordingTransport, transport::RusbTransport, Model, Options, TARGET_DEVICES};
+use crate::{config::Config, error::Error};
+
+/// A connected device matching one of [`TARGET_DEVICES`], which can be
+/// configured with [`Device::apply`] and [`Device::read`].
+pub struct Device {
+	pub device: rusb::Device<GlobalContext>,
+	pub model: &'static Model,
+}
+
+impl Device {
+	/// Wraps `device` if it matches one of [`TARGET_DEVICES`]. Devices whose
+	/// descriptor cannot be read are not matched.
+	pub fn new(device: rusb::Device<GlobalContext>) -> Option<Self> {
+		let descriptor = device.device_descriptor().ok()?;
+		let id = (descriptor.vendor_id(), descriptor.product_id());
+
+		TARGET_DEVICES
+			.iter()
+			.find(|model| (model.vendor_id, model.product_id) == id)
+			.map(|model| Self { device, model })
+	}
+
+	pub fn bus(&self) -> u8 {
+		self.device.bus_number()
+	}
+
+	pub fn address(&self) -> u8 {
+		self.device.address()
+	}
+
+	/// Port path in the same form as sysfs (`<bus>-<port>.<port>...`)
+	pub fn port_path(&self) -> String {
+		let ports = self
+			.device
+			.port_numbers()
+			.unwrap_or_default()
+			.iter()
+			.map(u8::to_string)
+			.collect::<Vec<_>>()
+			.join(".");
+
+		format!("{}-{ports}", self.bus())
+	}
+
+	/// Reads the serial number string of the device, if it has one.
+	pub fn serial(&self) -> Option<String> {
+		let descriptor = self.device.device_descriptor().ok()?;
+		descriptor.serial_number_string_index()?;
+
+		self.device
+			.open()
+			.ok()?
+			.read_serial_number_string_ascii(&descriptor)
+			.ok()
+			.filter(|serial| !serial.is_empty())
+	}
+
+	/// Every connected device matching one of [`TARGET_DEVICES`], in bus
+	/// order. See [`find_devices`].
+	///
+	/// # Errors
+	///
+	/// If there is an error loading the device list.
+	pub fn list() -> Result<Vec<Self>, Error> {
+		find_devices()
+	}
+
+	/// Applies `config` to the device, appending the session to
+	/// `options.record` if given.
+	///
+	/// # Errors
+	///
+	/// If a DPI or button is not supported by the model (see
+	/// [`Model::check_config`]), the config needs `options.experimental` (see
+	/// [`super::check_unconfirmed`]), the device cannot be opened, or see
+	/// [`super::write_config`]
+	pub fn apply(&self, config: &Config, options: &Options) -> Result<(), Error> {
+		self.model.check_config(config)?;
+		super::check_unconfirmed(config, options.experimental)?;
+
+		let transport = RusbTransport::new(open(self)?);
+		match &options.record {
+			Some(path) => {
+				let mut recording = RecordingTransport::new(transport);
+				let result = super::write_config(&mut recording, config, self.model);
+				recording.save(path)?;
+				result
+			}
+			None => super::write_config(&mut { transport }, config, self.model),
+		}
+	}
+
+	/// Reads the configuration of the device, appending the session to
+	/// `options.record` if given. Reading sends a guessed request to the
+	/// report the debounce time is written to, so it needs
+	/// `options.experimental`.
+	///
+	/// # Errors
+	///
+	/// If `options.experimental` is not set, the device cannot be opened, or
+	/// see [`super::read_config_from`]
+	pub fn read(&self, options: &Options) -> Result<Config, Error> {
+		super::allow_unconfirmed("reading the config from the mouse", options.experimental)?;
+
+		let transport = RusbTransport::new(open(self)?);
+		match &options.record {
+			Some(path) => {
+				let mut recording = RecordingTransport::new(transport);
+				let result = super::read_config_from(&mut recording, self.model);
+				recording.save(path)?;
+				result
+			}
+			None => super::read_config_from(&mut { transport }, self.model),
+		}
+	}
+}
+
+impl fmt::Display for Device {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{} ({:04x}:{:04x}) on bus {} port {} address {}",
+			self.model.name,
+			self.model.vendor_id,
+			self.model.product_id,
+			self.bus(),
+			self.port_path(),
+			self.address(),
+		)
+	}
+}
+
+/// Identifies which connected device(s) a command should act on.
+#[derive(Debug, Clone)]
+pub enum DeviceSelector {
+	/// Position in the output of `list-devices`
+	Index(usize),
+	BusAddress(u8, u8),
+	PortPath(String),
+	Serial(String),
+}
+
+impl DeviceSelector {
+	fn matches(&self, index: usize, device: &Device) -> bool {
+		match self {
+			Self::Index(i) => *i == index,
+			Self::BusAddress(bus, address) => (*bus, *address) == (device.bus(), device.address()),
+			Self::PortPath(path) => *path == device.port_path(),
+			Self::Serial(serial) => Some(serial) == device.serial().as_ref(),
+		}
+	}
+}
+
+impl TryFrom<&str> for DeviceSelector {
+	type Error = String;
+
+	fn try_from(value: &str) -> Result<Self, Self::Error> {
+		let invalid = || {
+			format!(
+				"could not parse `{value}` as a device (expected <index>, <bus>:<address>, \
+				 <bus>-<port path> or serial:<serial>)"
+			)
+		};
+
+		if let Some(serial) = value.strip_prefix("serial:") {
+			Ok(Self::Serial(serial.to_owned()))
+		} else if let Some((bus, address)) = value.split_once(':') {
+			Ok(Self::BusAddress(
+				u8::from_str(bus).map_err(|_| invalid())?,
+				u8::from_str(address).map_err(|_| invalid())?,
+			))
+		} else if let Some((bus, ports)) = value.split_once('-') {
+			if u8::from_str(bus).is_err()
+				|| ports.split('.').any(|port| u8::from_str(port).is_err())
+			{
+				return Err(invalid())
+			}
+
+			Ok(Self::PortPath(value.to_owned()))
+		} else {
+			usize::from_str(value)
+				.map(Self::Index)
+				.map_err(|_| invalid())
+		}
+	}
+}
+
+impl fmt::Display for DeviceSelector {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Index(index) => write!(f, "{index}"),
+			Self::BusAddress(bus, address) => write!(f, "{bus}:{address}"),
+			Self::PortPath(path) => write!(f, "{path}"),
+			Self::Serial(serial) => write!(f, "serial:{serial}"),
+		}
+	}
+}
+
+/// Finds every connected device matching one of [`TARGET_DEVICES`], sorted
+/// by bus and port path so that indices stay stable between runs.
+///
+/// # Errors
+///
+/// If there is an error loading the device list.
+pub fn find_devices() -> Result<Vec<Device>, Error> {
+	let mut devices = rusb::devices()
+		.map_err(|e| Error::usb("load usb device list", e))?
+		.iter()
+		.filter_map(Device::new)
+		.collect::<Vec<_>>();
+
+	devices.sort_by_cached_key(|device| {
+		(device.bus(), device.device.port_numbers().unwrap_or_default())
+	});
+	Ok(devices)
+}
+
+/// Finds the devices a command should act on.
+///
+/// With `all`, every connected device is returned. Otherwise the device
+/// matching `selector` is returned, or the first device if there is no
+/// selector.
+///
+/// # Errors
+///
+/// If no device matches, or the device list cannot be loaded.
+pub fn select_devices(selector: Option<&DeviceSelector>, all: bool) -> Result<Vec<Device>, Error> {
+	let devices = find_devices()?;
+
+	if devices.is_empty() {
+		return Err(Error::DeviceNotFound)
+	}
+
+	if all {
+		return Ok(devices)
+	}
+
+	match selector {
+		Some(selector) => {
+			let device = devices
+				.into_iter()
+				.enumerate()
+				.find(|(i, device)| selector.matches(*i, device))
+				.ok_or_else(|| Error::NoMatchingDevice(selector.to_string()))?
+				.1;
+
+			Ok(vec![device])
+		}
+		None => {
+			if devices.len() > 1 {
+				eprintln!(
+					"warning: {} devices connected, using {} (see `list-devices` and `--device`)",
+					devices.len(),
+					devices[0],
+				);
+			}
+
+			Ok(devices.into_iter().take(1).collect())
+		}
+	}
+}
+
+/// Opens `device` for configuration.
+///
+/// # Errors
+///
+/// If the device cannot be opened.
+pub fn open(device: &Device) -> Result<rusb::DeviceHandle<GlobalContext>, Error> {
+	device
+		.device
+		.open()
+		.map_err(|e| Error::usb("open usb device", e))
+}
@@ -0,0 +1,202 @@
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{config, error::Error, store};
+
+/// Formats `bytes` as hex, 16 bytes per line.
+pub fn format_hex(bytes: &[u8]) -> String {
+	let mut output = String::new();
+	for line in bytes.chunks(16) {
+		let line = line
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect::<Vec<_>>()
+			.join(" ");
+		let _ = writeln!(output, "{line}");
+	}
+	output
+}
+
+/// A golden test fixture: a config and the packets it is expected to
+/// produce.
+#[derive(Debug)]
+pub struct Fixture {
+	pub config: config::Config,
+	pub main_packet: [u8; 520],
+	pub buttons_packet: [u8; 520],
+	pub debounce_packet: [u8; 6],
+}
+
+impl Fixture {
+	/// Writes the fixture to `dir`: the config as `config.json` and the
+	/// packets as `main.hex`, `buttons.hex` and `debounce.hex`.
+	///
+	/// # Errors
+	///
+	/// If the fixture cannot be written.
+	pub fn write(&self, dir: &Path) -> Result<(), Error> {
+		fs::create_dir_all(dir)
+			.map_err(|e| Error::io(format!("create fixture directory {}", dir.display()), e))?;
+
+		store::save_config(&dir.join("config.json"), &self.config)?;
+
+		for (name, packet) in [
+			("main.hex", &self.main_packet[..]),
+			("buttons.hex", &self.buttons_packet[..]),
+			("debounce.hex", &self.debounce_packet[..]),
+		] {
+			let path = dir.join(name);
+			fs::write(&path, format_hex(packet))
+				.map_err(|e| Error::io(format!("write {}", path.display()), e))?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashSet, path::PathBuf};
+
+	use super::*;
+	use crate::{
+		config::{
+			buttons::ButtonAction,
+			lighting,
+			DebounceTime,
+			LiftoffDistance,
+			MouseButtonType,
+			PollingRate,
+		},
+		usb::{
+			build_buttons_packet,
+			build_debounce_packet,
+			build_main_packet,
+			decode,
+			layout,
+			TARGET_DEVICES,
+		},
+	};
+
+	fn read_packet<const N: usize>(dir: &Path, name: &str) -> [u8; N] {
+		let hex = fs::read_to_string(dir.join(name)).unwrap();
+		layout::parse_hex(&hex)
+			.unwrap()
+			.try_into()
+			.unwrap_or_else(|_| panic!("{} is not {N} bytes", dir.join(name).display()))
+	}
+
+	/// Every fixture in `tests/fixtures`, with its name.
+	fn fixtures() -> Vec<(String, Fixture)> {
+		let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+		let mut fixtures = fs::read_dir(&root)
+			.unwrap()
+			.map(|entry| entry.unwrap().path())
+			.filter(|dir| dir.is_dir())
+			.map(|dir| {
+				let fixture = Fixture {
+					// fixtures are never rewritten, older ones are upgraded in memory
+					config: store::read_config(&dir.join("config.json"))
+						.unwrap()
+						.unwrap()
+						.0,
+					main_packet: read_packet(&dir, "main.hex"),
+					buttons_packet: read_packet(&dir, "buttons.hex"),
+					debounce_packet: read_packet(&dir, "debounce.hex"),
+				};
+				(dir.file_name().unwrap().to_string_lossy().into_owned(), fixture)
+			})
+			.collect::<Vec<_>>();
+		fixtures.sort_by(|a, b| a.0.cmp(&b.0));
+		fixtures
+	}
+
+	/// Asserts that `found` contains every variant of `T`.
+	fn assert_covers<T: clap::ArgEnum>(found: impl IntoIterator<Item = T>, what: &str) {
+		let found = found
+			.into_iter()
+			.map(|variant| variant.to_possible_value().unwrap().get_name().to_owned())
+			.collect::<HashSet<_>>();
+
+		for variant in T::value_variants() {
+			let name = variant.to_possible_value().unwrap().get_name().to_owned();
+			assert!(found.contains(&name), "no fixture covers {what} {name}");
+		}
+	}
+
+	/// The fixtures were generated by the builders rather than captured from
+	/// the official software (see `tests/fixtures/README.md`), so this is a
+	/// snapshot test: it catches changes to the packets, not mistakes the
+	/// builders already made. Key and macro button codes have not been
+	/// confirmed at all, so no fixture covers them (see `check_unconfirmed`).
+	#[test]
+	fn builders_match_fixtures() {
+		// the Model D and Model O share a button layout, so either names the fixtures'
+		// buttons
+		let model_o = &TARGET_DEVICES[1];
+		let fixtures = fixtures();
+
+		for (name, fixture) in &fixtures {
+			let config = &fixture.config;
+			for (packet, built, expected) in [
+				("main", &build_main_packet(config).unwrap()[..], &fixture.main_packet[..]),
+				(
+					"buttons",
+					&build_buttons_packet(config, model_o).unwrap()[..],
+					&fixture.buttons_packet[..],
+				),
+				("debounce", &build_debounce_packet(config)[..], &fixture.debounce_packet[..]),
+			] {
+				assert!(
+					built == expected,
+					"{name}: {packet} packet differs from the fixture\nexpected:\n{}\nbuilt:\n{}",
+					layout::annotated_hexdump(expected),
+					layout::annotated_hexdump(built)
+				);
+			}
+
+			// decoding the fixture must give back a config that builds the same packets
+			let decoded = decode::decode_packets(
+				&fixture.main_packet,
+				&fixture.buttons_packet,
+				&fixture.debounce_packet,
+				model_o,
+			)
+			.unwrap_or_else(|e| panic!("{name}: {e}"));
+			assert_eq!(build_main_packet(&decoded).unwrap(), fixture.main_packet, "{name}");
+			assert_eq!(
+				build_buttons_packet(&decoded, model_o).unwrap(),
+				fixture.buttons_packet,
+				"{name}"
+			);
+			assert_eq!(build_debounce_packet(&decoded), fixture.debounce_packet, "{name}");
+		}
+
+		let configs = fixtures
+			.iter()
+			.map(|(_, fixture)| &fixture.config)
+			.collect::<Vec<_>>();
+		assert_covers::<lighting::Mode>(configs.iter().map(|c| c.lighting.mode), "LED mode");
+		assert_covers::<PollingRate>(configs.iter().map(|c| c.polling_rate), "polling rate");
+		assert_covers::<LiftoffDistance>(
+			configs.iter().map(|c| c.liftoff_distance),
+			"liftoff distance",
+		);
+		assert_covers::<DebounceTime>(configs.iter().map(|c| c.debounce_time), "debounce time");
+		assert_covers::<MouseButtonType>(
+			configs
+				.iter()
+				.flat_map(|c| model_o.button_actions(c))
+				.filter_map(|action| match action {
+					ButtonAction::Builtin(button) => Some(button),
+					ButtonAction::Key(_) | ButtonAction::Macro(_) => None,
+				}),
+			"button type",
+		);
+
+		let xy = configs
+			.iter()
+			.map(|c| c.dpi.iter().any(|dpi| dpi.x_dpi != dpi.y_dpi))
+			.collect::<HashSet<_>>();
+		assert_eq!(xy.len(), 2, "fixtures must cover XY independent DPI on and off");
+	}
+}
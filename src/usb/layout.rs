@@ -0,0 +1,680 @@
+use std::fmt::Write;
+
+use super::{BUTTON_SLOTS, TARGET_DEVICES};
+use crate::config::{
+	buttons::ButtonAction,
+	from_discriminant,
+	lighting,
+	macros::{MacroButton, MacroKey, MACRO_EVENTS},
+	DebounceTime,
+	LiftoffDistance,
+	PollingRate,
+};
+
+/// How the bytes of a [`FieldKind::Value`] field are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// XY independent flag and [`PollingRate`]
+	XyPollingRate,
+	/// Selected DPI (high nibble) and enabled DPI count (low nibble)
+	SelectedDpi,
+	/// Inverted bitset of enabled DPIs
+	DpiEnable,
+	/// DPI in hundreds
+	Dpi,
+	ColorRgb,
+	ColorRbg,
+	/// [`lighting::Mode`]
+	LedMode,
+	/// Speed in the low nibble, with a fixed high nibble of `4`
+	PrefixedSpeed,
+	/// [`lighting::RainbowDirection`]
+	RainbowDirection,
+	/// Brightness in the high nibble
+	Brightness,
+	/// Brightness (high nibble) and speed (low nibble)
+	BrightnessSpeed,
+	/// Speed stored as is
+	Speed,
+	/// [`LiftoffDistance`]
+	LiftoffDistance,
+	/// [`ButtonAction`]
+	Button,
+	/// [`DebounceTime`]
+	DebounceTime,
+	/// Number stored as is
+	Number,
+	/// [`MacroRepeat`](crate::config::macros::MacroRepeat) mode and count
+	MacroRepeat,
+	/// Macro event type and key or button
+	MacroEvent,
+	/// Delay in milliseconds (big endian)
+	Delay,
+}
+
+impl Format {
+	/// Describes the value `bytes` represent, or why they are invalid.
+	pub fn describe(self, bytes: &[u8]) -> String {
+		let byte = bytes[0];
+		let nibbles = (byte >> 4, byte & 0x0f);
+
+		match self {
+			Self::XyPollingRate => format!(
+				"XY independent {}, polling rate {}",
+				match byte & 0x08 != 0 {
+					true => "on",
+					false => "off",
+				},
+				enum_name(byte & !0x08, |r: PollingRate| r as u8)
+			),
+			Self::SelectedDpi => format!("DPI {} of {} selected", nibbles.0, nibbles.1),
+			Self::DpiEnable => {
+				let enabled = (0..6)
+					.filter(|i| !byte & (0b1 << i) != 0)
+					.map(|i| (i + 1).to_string())
+					.collect::<Vec<_>>();
+
+				match (enabled.is_empty(), !byte & 0b1100_0000 != 0) {
+					(_, true) => "invalid (bits set outside of the 6 DPIs)".to_owned(),
+					(true, false) => "no DPIs enabled".to_owned(),
+					(false, false) => format!("DPIs {} enabled", enabled.join(", ")),
+				}
+			}
+			Self::Dpi => match byte {
+				0 => "invalid (0)".to_owned(),
+				_ => format!("{}", byte as u32 * 100),
+			},
+			Self::ColorRgb => format!("#{:02x}{:02x}{:02x}", bytes[0], bytes[1], bytes[2]),
+			Self::ColorRbg => format!("#{:02x}{:02x}{:02x}", bytes[0], bytes[2], bytes[1]),
+			Self::LedMode => enum_name(byte, |m: lighting::Mode| m as u8),
+			Self::PrefixedSpeed => match nibbles.0 {
+				4 => format!("speed {}", nibbles.1),
+				_ => format!("speed {} (invalid prefix {:x})", nibbles.1, nibbles.0),
+			},
+			Self::RainbowDirection => enum_name(byte, |d: lighting::RainbowDirection| d as u8),
+			Self::Brightness => match nibbles.1 {
+				0 => format!("brightness {}", nibbles.0),
+				_ => format!("brightness {} (invalid low nibble {:x})", nibbles.0, nibbles.1),
+			},
+			Self::BrightnessSpeed => format!("brightness {}, speed {}", nibbles.0, nibbles.1),
+			Self::Speed => format!("speed {byte}"),
+			Self::LiftoffDistance => enum_name(byte, |d: LiftoffDistance| d as u8),
+			Self::Button => ButtonAction::from_code([bytes[0], bytes[1], bytes[2], bytes[3]])
+				.map(|action| action.to_string())
+				.unwrap_or_else(|| "invalid (unknown value)".to_owned()),
+			Self::DebounceTime => enum_name(byte, |t: DebounceTime| t as u8),
+			Self::Number => format!("{byte}"),
+			Self::MacroRepeat => match byte {
+				0x01 => format!("play {} times", bytes[1]),
+				0x02 => "repeat while held".to_owned(),
+				0x03 => "toggle repeating".to_owned(),
+				_ => "invalid (unknown repeat mode)".to_owned(),
+			},
+			Self::MacroEvent => match byte {
+				0x81 => format!("key down {}", MacroKey(bytes[1])),
+				0x01 => format!("key up {}", MacroKey(bytes[1])),
+				0x82 => format!("button down {}", enum_name(bytes[1], |b: MacroButton| b as u8)),
+				0x02 => format!("button up {}", enum_name(bytes[1], |b: MacroButton| b as u8)),
+				_ => "invalid (unknown event type)".to_owned(),
+			},
+			Self::Delay => format!("{} ms", u16::from_be_bytes([bytes[0], bytes[1]])),
+		}
+	}
+}
+
+/// CLI name of the variant of `T` with the given discriminant.
+fn enum_name<T, D>(value: D, discriminant: fn(T) -> D) -> String
+where
+	T: clap::ArgEnum + Copy,
+	D: PartialEq,
+{
+	from_discriminant(value, discriminant)
+		.and_then(|variant| variant.to_possible_value())
+		.map(|value| value.get_name().to_owned())
+		.unwrap_or_else(|| "invalid (unknown value)".to_owned())
+}
+
+/// What a [`Field`] of a packet is known to contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+	/// Report id and command, which identify the packet
+	Header,
+	/// A setting which is understood
+	Value(Format),
+	/// Data which has not been identified (kept in the `raw` config section
+	/// where applicable)
+	Unknown,
+	/// Zeros with no known meaning
+	Padding,
+}
+
+/// A range of bytes in a packet, as described by `packet_spec.md`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+	pub offset: usize,
+	pub len: usize,
+	pub name: String,
+	pub kind: FieldKind,
+}
+
+/// Which packet of `packet_spec.md` a byte string is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketType {
+	/// `Main Packet` `Type 1`
+	Main,
+	/// `Main Packet` `Type 2`
+	Buttons,
+	/// `Debounce Packet`
+	Debounce,
+	/// `Main Packet` `Type 3`
+	Macro,
+}
+
+impl PacketType {
+	/// Identifies a packet from its header.
+	pub fn from_header(packet: &[u8]) -> Option<Self> {
+		match packet.get(..2)? {
+			[0x04, 0x11] => Some(Self::Main),
+			[0x04, 0x12] => Some(Self::Buttons),
+			[0x05, 0x1a] => Some(Self::Debounce),
+			[0x04, 0x13] => Some(Self::Macro),
+			_ => None,
+		}
+	}
+
+	/// Identifies a packet from its header and length.
+	pub fn identify(packet: &[u8]) -> Option<Self> {
+		Self::from_header(packet).filter(|packet_type| packet_type.len() == packet.len())
+	}
+
+	/// Length of the packet in bytes.
+	#[allow(clippy::len_without_is_empty)] // packets are never empty
+	pub fn len(self) -> usize {
+		match self {
+			Self::Main | Self::Buttons | Self::Macro => 520,
+			Self::Debounce => 6,
+		}
+	}
+
+	/// Field layout of `packet`, which must be of this type.
+	pub fn layout(self, packet: &[u8]) -> Vec<Field> {
+		match self {
+			Self::Main => main_packet_layout(packet.get(10).is_some_and(|byte| byte & 0x08 != 0)),
+			Self::Buttons => buttons_packet_layout(),
+			Self::Debounce => debounce_packet_layout(),
+			Self::Macro => macro_packet_layout(packet.get(5).copied().unwrap_or_default()),
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Main => "main packet (type 1)",
+			Self::Buttons => "main packet (type 2, buttons)",
+			Self::Debounce => "debounce packet",
+			Self::Macro => "main packet (type 3, macro)",
+		}
+	}
+}
+
+/// Appends fields to a layout, tracking the current offset like the packet
+/// builders' cursors.
+struct LayoutBuilder(Vec<Field>);
+
+impl LayoutBuilder {
+	fn field(&mut self, len: usize, kind: FieldKind, name: impl Into<String>) -> &mut Self {
+		let offset = self
+			.0
+			.last()
+			.map(|field| field.offset + field.len)
+			.unwrap_or(0);
+
+		self.0.push(Field {
+			offset,
+			len,
+			name: name.into(),
+			kind,
+		});
+		self
+	}
+
+	fn value(&mut self, len: usize, format: Format, name: impl Into<String>) -> &mut Self {
+		self.field(len, FieldKind::Value(format), name)
+	}
+
+	fn unknown(&mut self, len: usize, name: impl Into<String>) -> &mut Self {
+		self.field(len, FieldKind::Unknown, name)
+	}
+}
+
+/// Field layout of the `Type 1` main packet. The DPI block depends on
+/// whether XY independent DPIs are enabled.
+pub fn main_packet_layout(separate_xy_dpi: bool) -> Vec<Field> {
+	let mut layout = LayoutBuilder(Vec::new());
+
+	layout
+		.field(2, FieldKind::Header, "report id / command")
+		.unknown(8, "unknown (raw.header)")
+		.value(1, Format::XyPollingRate, "XY independent / polling rate")
+		.value(1, Format::SelectedDpi, "selected DPI / enabled DPI count")
+		.value(1, Format::DpiEnable, "DPI enable state (bitset)");
+
+	if separate_xy_dpi {
+		for i in 1..=6 {
+			layout
+				.value(1, Format::Dpi, format!("DPI {i} X (hundreds)"))
+				.value(1, Format::Dpi, format!("DPI {i} Y (hundreds)"));
+		}
+	} else {
+		for i in 1..=6 {
+			layout.value(1, Format::Dpi, format!("DPI {i} (hundreds)"));
+		}
+		layout.field(6, FieldKind::Padding, "padding (XY independent off)");
+	}
+
+	layout.unknown(4, "unknown (raw.after_dpi)");
+	for i in 1..=6 {
+		layout.value(3, Format::ColorRgb, format!("DPI color {i} (RGB)"));
+	}
+
+	layout
+		.unknown(6, "unknown (raw.after_dpi_colors)")
+		.value(1, Format::LedMode, "LED mode")
+		.value(1, Format::PrefixedSpeed, "rainbow speed")
+		.value(1, Format::RainbowDirection, "rainbow direction")
+		.value(1, Format::Brightness, "solid brightness")
+		.value(3, Format::ColorRbg, "solid color (RBG)")
+		.unknown(2, "unknown (raw.before_breathing)");
+
+	for i in 1..=7 {
+		layout.value(3, Format::ColorRbg, format!("breathing color {i} (RBG)"));
+	}
+
+	layout
+		.value(1, Format::BrightnessSpeed, "tail brightness / speed")
+		.value(1, Format::PrefixedSpeed, "fade speed")
+		.unknown(31, "unknown (raw.after_fade)")
+		.value(1, Format::BrightnessSpeed, "rave brightness / speed")
+		.value(3, Format::ColorRbg, "rave color 1 (RBG)")
+		.value(3, Format::ColorRbg, "rave color 2 (RBG)")
+		.unknown(1, "unknown (raw.before_wave)")
+		.value(1, Format::BrightnessSpeed, "wave brightness / speed")
+		.value(1, Format::Speed, "breathing (single) speed")
+		.value(3, Format::ColorRbg, "breathing (single) color (RBG)")
+		.value(1, Format::LiftoffDistance, "liftoff distance")
+		.field(390, FieldKind::Padding, "padding");
+
+	layout.0
+}
+
+/// Field layout of the `Type 2` (buttons) main packet. Slots are labelled
+/// with the names of the buttons known models have in them.
+pub fn buttons_packet_layout() -> Vec<Field> {
+	let mut layout = LayoutBuilder(Vec::new());
+
+	layout
+		.field(2, FieldKind::Header, "report id / command")
+		.unknown(6, "unknown");
+
+	for slot in 0..BUTTON_SLOTS {
+		let mut names = Vec::new();
+		for button in TARGET_DEVICES
+			.iter()
+			.filter_map(|model| model.buttons.get(slot))
+		{
+			if !names.contains(&button.name) {
+				names.push(button.name);
+			}
+		}
+
+		layout.value(4, Format::Button, match names[..] {
+			[] => format!("slot {slot} button (unused)"),
+			_ => format!("slot {slot} button ({})", names.join(" / ")),
+		});
+	}
+
+	layout.field(436, FieldKind::Padding, "padding");
+
+	layout.0
+}
+
+/// Field layout of the debounce packet.
+pub fn debounce_packet_layout() -> Vec<Field> {
+	let mut layout = LayoutBuilder(Vec::new());
+
+	layout
+		.field(2, FieldKind::Header, "report id / command")
+		.value(1, Format::DebounceTime, "debounce time")
+		.field(3, FieldKind::Padding, "padding");
+
+	layout.0
+}
+
+/// Field layout of the `Type 3` (macro) main packet containing
+/// `event_count` events.
+pub fn macro_packet_layout(event_count: u8) -> Vec<Field> {
+	let event_count = (event_count as usize).min(MACRO_EVENTS);
+	let mut layout = LayoutBuilder(Vec::new());
+
+	layout
+		.field(2, FieldKind::Header, "report id / command")
+		.value(1, Format::Number, "macro slot")
+		.value(2, Format::MacroRepeat, "repeat mode / count")
+		.value(1, Format::Number, "event count")
+		.field(2, FieldKind::Padding, "padding");
+
+	for i in 1..=event_count {
+		layout
+			.value(2, Format::MacroEvent, format!("event {i}"))
+			.value(2, Format::Delay, format!("delay after event {i}"));
+	}
+
+	layout.field(512 - event_count * 4, FieldKind::Padding, "padding");
+
+	layout.0
+}
+
+/// Field layout of `packet`, or [`None`] if it is not a known packet.
+pub fn layout(packet: &[u8]) -> Option<Vec<Field>> {
+	PacketType::identify(packet).map(|packet_type| packet_type.layout(packet))
+}
+
+/// Parses hex bytes, ignoring whitespace, `:` separators and a `0x`
+/// prefix (as copied from usbmon or Wireshark).
+pub fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+	let digits = hex
+		.trim()
+		.trim_start_matches("0x")
+		.chars()
+		.filter(|c| !c.is_whitespace() && *c != ':')
+		.collect::<Vec<_>>();
+
+	if digits.len() % 2 != 0 {
+		return Err("hex data has an odd number of digits".to_owned())
+	}
+
+	digits
+		.chunks(2)
+		.map(|pair| {
+			let pair = pair.iter().collect::<String>();
+			u8::from_str_radix(&pair, 16).map_err(|_| format!("`{pair}` is not a hex byte"))
+		})
+		.collect()
+}
+
+/// Formats `bytes` as hex, collapsing long runs of one value.
+pub fn format_bytes(bytes: &[u8]) -> String {
+	match bytes {
+		[first, rest @ ..] if rest.len() >= 8 && rest.iter().all(|byte| byte == first) =>
+			format!("{first:02x} x{}", bytes.len()),
+		_ => bytes
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect::<Vec<_>>()
+			.join(" "),
+	}
+}
+
+/// Describes the contents of `field` in `packet`, or [`None`] if there is
+/// nothing to say beyond its name.
+pub fn describe(field: &Field, packet: &[u8]) -> Option<String> {
+	let bytes = &packet[field.offset..field.offset + field.len];
+
+	match field.kind {
+		FieldKind::Value(format) => Some(format.describe(bytes)),
+		FieldKind::Padding if bytes.iter().any(|byte| *byte != 0) =>
+			Some("unexpected non-zero padding".to_owned()),
+		FieldKind::Header | FieldKind::Unknown | FieldKind::Padding => None,
+	}
+}
+
+/// Formats `packet` with one line per field of `fields`: offset, raw bytes,
+/// meaning and decoded value. Fields which are not entirely in `packet` are
+/// skipped.
+pub fn format_fields(packet: &[u8], fields: &[Field]) -> String {
+	let mut output = String::new();
+
+	for field in fields
+		.iter()
+		.filter(|field| field.offset + field.len <= packet.len())
+	{
+		let bytes = format_bytes(&packet[field.offset..field.offset + field.len]);
+		let mut annotation = field.name.clone();
+		if let Some(description) = describe(field, packet) {
+			let _ = write!(annotation, " = {description}");
+		}
+
+		for (i, chunk) in bytes.split(' ').collect::<Vec<_>>().chunks(16).enumerate() {
+			let _ = match i {
+				0 =>
+					writeln!(output, "{:04x}  {:<48}  {annotation}", field.offset, chunk.join(" ")),
+				_ => writeln!(output, "      {}", chunk.join(" ")),
+			};
+		}
+	}
+
+	output
+}
+
+/// Breaks `packet` down field by field: a line naming the packet, then one
+/// line per field (see [`format_fields`]). Packets of the wrong length are
+/// broken down as far as their complete fields go. Returns [`None`] if the
+/// packet does not start with a known header.
+pub fn dissect(packet: &[u8]) -> Option<String> {
+	let packet_type = PacketType::from_header(packet)?;
+
+	let mut output = format!("{} ({} bytes", packet_type.name(), packet.len());
+	if packet.len() != packet_type.len() {
+		let _ = write!(output, ", expected {} - only complete fields are shown", packet_type.len());
+	}
+	output.push_str(")\n");
+	output.push_str(&format_fields(packet, &packet_type.layout(packet)));
+
+	Some(output)
+}
+
+/// Formats `packet` as a hexdump with one line per field of its layout.
+/// Unknown packets are dumped 16 bytes per line.
+pub fn annotated_hexdump(packet: &[u8]) -> String {
+	match layout(packet) {
+		Some(fields) => format_fields(packet, &fields),
+		None => packet
+			.chunks(16)
+			.enumerate()
+			.map(|(i, chunk)| format!("{:04x}  {}\n", i * 16, format_bytes(chunk)))
+			.collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_contiguous(fields: &[Field], len: usize) {
+		let mut offset = 0;
+		for field in fields {
+			assert_eq!(field.offset, offset, "{} is not contiguous", field.name);
+			offset += field.len;
+		}
+		assert_eq!(offset, len);
+	}
+
+	#[test]
+	fn layouts_cover_packets() {
+		assert_contiguous(&main_packet_layout(false), 520);
+		assert_contiguous(&main_packet_layout(true), 520);
+		assert_contiguous(&buttons_packet_layout(), 520);
+		assert_contiguous(&debounce_packet_layout(), 6);
+		for event_count in [0, 1, MACRO_EVENTS as u8] {
+			assert_contiguous(&macro_packet_layout(event_count), 520);
+		}
+	}
+
+	/// Asserts that every byte which differs between `old` and `new` is in a
+	/// field of the layout of `new` whose name contains one of `names`.
+	fn assert_changes_named(old: &[u8], new: &[u8], names: &[&str]) {
+		let fields = layout(new).unwrap();
+		let changed = (0..new.len())
+			.filter(|&offset| old[offset] != new[offset])
+			.collect::<Vec<_>>();
+		assert!(!changed.is_empty(), "changing {names:?} did not change the packet");
+
+		for offset in changed {
+			let field = fields
+				.iter()
+				.find(|field| (field.offset..field.offset + field.len).contains(&offset))
+				.unwrap();
+			assert!(
+				names.iter().any(|name| field.name.contains(name))
+					&& field.kind != FieldKind::Padding,
+				"changing {names:?} changed byte {offset:#x}, which the layout names `{}`",
+				field.name
+			);
+		}
+	}
+
+	/// Changes every setting the builders encode and checks that the layouts
+	/// name the bytes the builders changed, so the layouts cannot drift from
+	/// the builders.
+	#[test]
+	fn layouts_name_changed_bytes() {
+		use crate::{
+			config::{
+				macros::{Macro, MacroRepeat},
+				Color,
+				Config,
+				MouseButtonType,
+				RangedByte,
+			},
+			usb::{
+				build_buttons_packet,
+				build_debounce_packet,
+				build_macro_packet,
+				build_main_packet,
+			},
+		};
+
+		let model = &TARGET_DEVICES[0];
+		// names the changed bytes may have, and how to change the config
+		type Mutation = (&'static [&'static str], fn(&mut Config));
+		let mutations: &[Mutation] = &[
+			(&["polling rate"], |c| c.polling_rate = PollingRate::_125hz),
+			(&["XY independent", "X (hundreds)", "Y (hundreds)"], |c| c.dpi[1].y_dpi = 1600),
+			(&["selected DPI"], |c| c.current_dpi = RangedByte(1)),
+			(&["DPI enable", "enabled DPI count"], |c| c.dpi[3].enable = true),
+			(&["DPI 3 (hundreds)"], |c| {
+				c.dpi[2].x_dpi = 3200;
+				c.dpi[2].y_dpi = 3200;
+			}),
+			(&["DPI color 2"], |c| c.dpi[1].color = Color { r: 1, g: 2, b: 3 }),
+			(&["raw.header"], |c| c.raw.header.0[0] = 0x55),
+			(&["raw.after_fade"], |c| c.raw.after_fade.0[5] = 0x55),
+			(&["LED mode"], |c| c.lighting.mode = lighting::Mode::Rave),
+			(&["rainbow speed"], |c| c.lighting.rainbow.speed = RangedByte(3)),
+			(&["rainbow direction"], |c| {
+				c.lighting.rainbow.direction = lighting::RainbowDirection::Forward
+			}),
+			(&["solid brightness"], |c| c.lighting.solid.brightness = RangedByte(1)),
+			(&["solid color"], |c| c.lighting.solid.color = Color { r: 1, g: 2, b: 3 }),
+			(&["breathing color 4"], |c| {
+				c.lighting.breathing.colors[3] = Color { r: 1, g: 2, b: 3 }
+			}),
+			(&["tail brightness / speed"], |c| c.lighting.tail.speed = RangedByte(3)),
+			(&["fade speed"], |c| c.lighting.fade.speed = RangedByte(3)),
+			(&["rave brightness / speed"], |c| c.lighting.rave.brightness = RangedByte(1)),
+			(&["rave color 2"], |c| c.lighting.rave.colors[1] = Color { r: 1, g: 2, b: 3 }),
+			(&["wave brightness / speed"], |c| c.lighting.wave.speed = RangedByte(3)),
+			(&["breathing (single) speed"], |c| {
+				c.lighting.breathing_single.speed = RangedByte(3)
+			}),
+			(&["breathing (single) color"], |c| {
+				c.lighting.breathing_single.color = Color { r: 1, g: 2, b: 3 }
+			}),
+			(&["liftoff distance"], |c| c.liftoff_distance = LiftoffDistance::_3mm),
+			(&["(back)"], |c| {
+				c.buttons
+					.0
+					.insert("back".to_owned(), ButtonAction::Builtin(MouseButtonType::MiddleClick));
+			}),
+			(&["slot 7"], |c| {
+				c.buttons.0.insert(
+					"slot-7".to_owned(),
+					ButtonAction::Builtin(MouseButtonType::MiddleClick),
+				);
+			}),
+			(&["debounce time"], |c| c.debounce_time = DebounceTime::_16ms),
+		];
+
+		let packets = |config: &Config| {
+			[
+				build_main_packet(config).unwrap().to_vec(),
+				build_buttons_packet(config, model).unwrap().to_vec(),
+				build_debounce_packet(config).to_vec(),
+			]
+		};
+		let default_packets = packets(&Config::default());
+
+		for (names, mutate) in mutations {
+			let mut config = Config::default();
+			mutate(&mut config);
+
+			let changed = packets(&config)
+				.into_iter()
+				.zip(&default_packets)
+				.filter(|(new, old)| new != *old)
+				.collect::<Vec<_>>();
+			assert_eq!(changed.len(), 1, "changing {names:?} changed {} packets", changed.len());
+			assert_changes_named(changed[0].1, &changed[0].0, names);
+		}
+
+		let base = Macro::parse("key-down a\ndelay 10\nkey-up a").unwrap();
+		// name of the changed bytes, slot to store the macro in, and how to change it
+		type MacroMutation = (&'static str, u8, fn(&mut Macro));
+		let macro_mutations: &[MacroMutation] = &[
+			("macro slot", 2, |_| {}),
+			("repeat mode / count", 1, |m| m.repeat = MacroRepeat::Toggle),
+			("delay after event 1", 1, |m| *m = Macro::parse("key-down a\nkey-up a").unwrap()),
+			("event 2", 1, |m| *m = Macro::parse("key-down a\ndelay 10\nkey-up b").unwrap()),
+		];
+		let old = build_macro_packet(1, &base).unwrap();
+		for (name, slot, mutate) in macro_mutations {
+			let mut macro_ = base.clone();
+			mutate(&mut macro_);
+			assert_changes_named(&old, &build_macro_packet(*slot, &macro_).unwrap(), &[name]);
+		}
+	}
+
+	#[test]
+	fn describes_builder_output() {
+		let config = crate::config::Config::default();
+
+		for packet in [
+			&super::super::build_main_packet(&config).unwrap()[..],
+			&super::super::build_buttons_packet(&config, &TARGET_DEVICES[0]).unwrap()[..],
+			&super::super::build_debounce_packet(&config)[..],
+		] {
+			for field in layout(packet).unwrap() {
+				if let Some(description) = describe(&field, packet) {
+					assert!(
+						!description.contains("invalid") && !description.contains("unexpected"),
+						"{}: {description}",
+						field.name
+					);
+				}
+			}
+		}
+
+		let main = super::super::build_main_packet(&config).unwrap();
+		let fields = layout(&main).unwrap();
+		let describe_named =
+			|name: &str| describe(fields.iter().find(|f| f.name == name).unwrap(), &main);
+		assert_eq!(describe_named("LED mode").as_deref(), Some("off"));
+		assert_eq!(describe_named("DPI 1 (hundreds)").as_deref(), Some("400"));
+	}
+
+	#[test]
+	fn parses_hex() {
+		assert_eq!(parse_hex("0x04 11:7b\n00"), Ok(vec![0x04, 0x11, 0x7b, 0x00]));
+		assert!(parse_hex("041").is_err());
+		assert!(parse_hex("zz").is_err());
+	}
+}
@@ -0,0 +1,164 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{layout::parse_hex, transport::Transport};
+
+/// A single control transfer as seen on the wire.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlTransfer {
+	pub request_type: u8,
+	pub request: u8,
+	pub value: u16,
+	pub index: u16,
+	#[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+	pub data: Vec<u8>,
+}
+
+fn serialize_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str(
+		&data
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect::<Vec<_>>()
+			.join(" "),
+	)
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+	parse_hex(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// An operation performed on a [`MockDevice`], in the order it happened.
+/// Also the format of session files (see [`super::record`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MockEvent {
+	Claim(u8),
+	Release(u8),
+	Write(ControlTransfer),
+	Read(ControlTransfer),
+}
+
+/// In-memory [`Transport`] which records every operation performed on it.
+///
+/// Control transfers are only accepted on claimed interfaces (the interface
+/// is taken from `index`), mirroring the behavior of a real device.
+/// Reads are answered in order from the queues in `responses`, keyed by
+/// `(request, value, index)`.
+#[derive(Debug, Default)]
+pub struct MockDevice {
+	pub events: Vec<MockEvent>,
+	pub claimed: Vec<u8>,
+	pub responses: HashMap<(u8, u16, u16), VecDeque<Vec<u8>>>,
+}
+
+impl MockDevice {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Every control transfer written to the device, in order.
+	pub fn writes(&self) -> Vec<&ControlTransfer> {
+		self.events
+			.iter()
+			.filter_map(|event| match event {
+				MockEvent::Write(transfer) => Some(transfer),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// Queues `data` to be returned by the next read matching
+	/// `(request, value, index)`.
+	pub fn respond(&mut self, request: u8, value: u16, index: u16, data: &[u8]) {
+		self.responses
+			.entry((request, value, index))
+			.or_default()
+			.push_back(data.to_vec());
+	}
+
+	fn check_claimed(&self, index: u16) -> rusb::Result<()> {
+		match self.claimed.contains(&(index as u8)) {
+			true => Ok(()),
+			false => Err(rusb::Error::NotFound),
+		}
+	}
+}
+
+impl Transport for MockDevice {
+	fn claim_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		if self.claimed.contains(&interface) {
+			return Err(rusb::Error::Busy)
+		}
+
+		self.claimed.push(interface);
+		self.events.push(MockEvent::Claim(interface));
+		Ok(())
+	}
+
+	fn release_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		let i = self
+			.claimed
+			.iter()
+			.position(|&i| i == interface)
+			.ok_or(rusb::Error::NotFound)?;
+
+		self.claimed.remove(i);
+		self.events.push(MockEvent::Release(interface));
+		Ok(())
+	}
+
+	fn write_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &[u8],
+		_timeout: Duration,
+	) -> rusb::Result<usize> {
+		self.check_claimed(index)?;
+
+		self.events.push(MockEvent::Write(ControlTransfer {
+			request_type,
+			request,
+			value,
+			index,
+			data: buf.to_vec(),
+		}));
+		Ok(buf.len())
+	}
+
+	fn read_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &mut [u8],
+		_timeout: Duration,
+	) -> rusb::Result<usize> {
+		self.check_claimed(index)?;
+
+		let response = self
+			.responses
+			.get_mut(&(request, value, index))
+			.and_then(VecDeque::pop_front)
+			.ok_or(rusb::Error::Pipe)?;
+		let len = response.len().min(buf.len());
+		buf[..len].copy_from_slice(&response[..len]);
+
+		self.events.push(MockEvent::Read(ControlTransfer {
+			request_type,
+			request,
+			value,
+			index,
+			data: buf[..len].to_vec(),
+		}));
+		Ok(len)
+	}
+}
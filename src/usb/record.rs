@@ -0,0 +1,403 @@
+#[cfg(any(test, feature = "replay"))]
+use std::collections::VecDeque;
+use std::{
+	fs::{self, OpenOptions},
+	io::Write,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+#[cfg(any(test, feature = "replay"))]
+use super::layout::format_bytes;
+use super::{
+	mock::{ControlTransfer, MockEvent},
+	transport::Transport,
+};
+use crate::error::Error;
+
+/// Path of the session file for the device at `port_path` when every device
+/// is recorded separately: `session.jsonl` becomes `session.<port
+/// path>.jsonl`.
+pub fn device_session_path(path: &Path, port_path: &str) -> PathBuf {
+	let mut name = path.file_stem().unwrap_or_default().to_owned();
+	name.push(format!(".{port_path}"));
+	if let Some(extension) = path.extension() {
+		name.push(".");
+		name.push(extension);
+	}
+	path.with_file_name(name)
+}
+
+/// Creates an empty session file at `path`, replacing an existing one.
+/// Sessions are appended to by every [`RecordingTransport::save`], so this
+/// is done once before the first transfer.
+///
+/// # Errors
+///
+/// If the session file cannot be created.
+pub fn start_session(path: &Path) -> Result<(), Error> {
+	fs::File::create(path)
+		.map(drop)
+		.map_err(|e| Error::io(format!("create session file {}", path.display()), e))
+}
+
+/// [`Transport`] which forwards every operation to another transport and
+/// records the successful ones, to be appended to a session file (one JSON
+/// [`MockEvent`] per line) with [`RecordingTransport::save`]. Read events
+/// hold the data returned by the device.
+pub struct RecordingTransport<T: Transport> {
+	inner: T,
+	events: Vec<MockEvent>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+	pub fn new(inner: T) -> Self {
+		Self {
+			inner,
+			events: Vec::new(),
+		}
+	}
+
+	/// Appends the recorded events to the session file at `path`.
+	///
+	/// # Errors
+	///
+	/// If the session file cannot be written.
+	pub fn save(self, path: &Path) -> Result<(), Error> {
+		let write_error = |e| Error::io(format!("write session file {}", path.display()), e);
+
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(write_error)?;
+
+		for event in &self.events {
+			let line = serde_json::to_string(event).map_err(|e| write_error(e.into()))?;
+			writeln!(file, "{line}").map_err(write_error)?;
+		}
+
+		Ok(())
+	}
+
+	fn record<R>(
+		&mut self,
+		result: rusb::Result<R>,
+		event: impl FnOnce() -> MockEvent,
+	) -> rusb::Result<R> {
+		if result.is_ok() {
+			self.events.push(event());
+		}
+
+		result
+	}
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+	fn claim_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		let result = self.inner.claim_interface(interface);
+		self.record(result, || MockEvent::Claim(interface))
+	}
+
+	fn release_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		let result = self.inner.release_interface(interface);
+		self.record(result, || MockEvent::Release(interface))
+	}
+
+	fn write_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &[u8],
+		timeout: Duration,
+	) -> rusb::Result<usize> {
+		let result = self
+			.inner
+			.write_control(request_type, request, value, index, buf, timeout);
+		self.record(result, || {
+			MockEvent::Write(ControlTransfer {
+				request_type,
+				request,
+				value,
+				index,
+				data: buf.to_vec(),
+			})
+		})
+	}
+
+	fn read_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &mut [u8],
+		timeout: Duration,
+	) -> rusb::Result<usize> {
+		let result = self
+			.inner
+			.read_control(request_type, request, value, index, buf, timeout);
+		let data = match result {
+			Ok(len) => buf[..len].to_vec(),
+			Err(_) => Vec::new(),
+		};
+		self.record(result, || {
+			MockEvent::Read(ControlTransfer {
+				request_type,
+				request,
+				value,
+				index,
+				data,
+			})
+		})
+	}
+}
+
+/// [`Transport`] which plays back a session file written by
+/// [`RecordingTransport`]. Every operation must match the next recorded
+/// event exactly (including the bytes written), otherwise it panics with
+/// both events, failing the test using it.
+///
+/// Only available in tests and with the `replay` feature.
+#[cfg(any(test, feature = "replay"))]
+pub struct ReplayTransport {
+	events: VecDeque<MockEvent>,
+}
+
+#[cfg(any(test, feature = "replay"))]
+impl ReplayTransport {
+	/// Parses a session in the format written by [`RecordingTransport`].
+	///
+	/// # Panics
+	///
+	/// If a line is not a valid event.
+	pub fn from_session(session: &str) -> Self {
+		Self {
+			events: session
+				.lines()
+				.filter(|line| !line.trim().is_empty())
+				.enumerate()
+				.map(|(i, line)| {
+					serde_json::from_str(line)
+						.unwrap_or_else(|e| panic!("invalid event on line {}: {e}", i + 1))
+				})
+				.collect(),
+		}
+	}
+
+	/// Loads a session file written by [`RecordingTransport`].
+	///
+	/// # Panics
+	///
+	/// If the file cannot be read or contains an invalid event.
+	pub fn open(path: &Path) -> Self {
+		Self::from_session(
+			&fs::read_to_string(path)
+				.unwrap_or_else(|e| panic!("could not read session file {}: {e}", path.display())),
+		)
+	}
+
+	/// Checks that every recorded event was replayed.
+	///
+	/// # Panics
+	///
+	/// If events are left over.
+	pub fn finish(self) {
+		if !self.events.is_empty() {
+			panic!(
+				"{} recorded event(s) were not replayed, starting with {}",
+				self.events.len(),
+				describe(&self.events[0])
+			)
+		}
+	}
+
+	/// Takes the next event, which must equal `actual`.
+	fn expect(&mut self, actual: MockEvent) -> MockEvent {
+		// interfaces are still released while unwinding from a mismatch
+		if std::thread::panicking() {
+			return actual
+		}
+
+		let expected = self.events.pop_front().unwrap_or_else(|| {
+			panic!("unexpected {} after the end of the session", describe(&actual))
+		});
+
+		let matches = match (&expected, &actual) {
+			// the data of a read comes from the recording
+			(MockEvent::Read(expected), MockEvent::Read(actual)) =>
+				ControlTransfer {
+					data: Vec::new(),
+					..expected.clone()
+				} == *actual,
+			_ => expected == actual,
+		};
+
+		if !matches {
+			panic!(
+				"replay mismatch\n  expected {}\n  found    {}",
+				describe(&expected),
+				describe(&actual)
+			)
+		}
+
+		expected
+	}
+}
+
+/// Formats an event for replay failures.
+#[cfg(any(test, feature = "replay"))]
+fn describe(event: &MockEvent) -> String {
+	match event {
+		MockEvent::Claim(interface) => format!("claim of interface {interface}"),
+		MockEvent::Release(interface) => format!("release of interface {interface}"),
+		MockEvent::Write(transfer) | MockEvent::Read(transfer) => format!(
+			"{} bmRequestType={:#04x} bRequest={:#04x} wValue={:#06x} wIndex={:#06x} data=[{}]",
+			match event {
+				MockEvent::Write(_) => "write",
+				_ => "read",
+			},
+			transfer.request_type,
+			transfer.request,
+			transfer.value,
+			transfer.index,
+			format_bytes(&transfer.data)
+		),
+	}
+}
+
+#[cfg(any(test, feature = "replay"))]
+impl Transport for ReplayTransport {
+	fn claim_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		self.expect(MockEvent::Claim(interface));
+		Ok(())
+	}
+
+	fn release_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		self.expect(MockEvent::Release(interface));
+		Ok(())
+	}
+
+	fn write_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &[u8],
+		_timeout: Duration,
+	) -> rusb::Result<usize> {
+		self.expect(MockEvent::Write(ControlTransfer {
+			request_type,
+			request,
+			value,
+			index,
+			data: buf.to_vec(),
+		}));
+		Ok(buf.len())
+	}
+
+	fn read_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &mut [u8],
+		_timeout: Duration,
+	) -> rusb::Result<usize> {
+		let expected = self.expect(MockEvent::Read(ControlTransfer {
+			request_type,
+			request,
+			value,
+			index,
+			data: Vec::new(),
+		}));
+
+		let data = match expected {
+			MockEvent::Read(transfer) => transfer.data,
+			_ => unreachable!(),
+		};
+		let len = data.len().min(buf.len());
+		buf[..len].copy_from_slice(&data[..len]);
+		Ok(len)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		config::Config,
+		usb::{mock::MockDevice, TARGET_DEVICES},
+	};
+
+	fn session_path(name: &str) -> std::path::PathBuf {
+		let path = std::env::temp_dir()
+			.join(format!("glorious-mouse-control-{}-{name}.jsonl", std::process::id()));
+		let _ = fs::remove_file(&path);
+		path
+	}
+
+	#[test]
+	fn names_device_sessions_after_port() {
+		assert_eq!(
+			device_session_path(Path::new("/tmp/session.jsonl"), "1-2.3"),
+			Path::new("/tmp/session.1-2.3.jsonl")
+		);
+		assert_eq!(device_session_path(Path::new("session"), "3-1"), Path::new("session.3-1"));
+	}
+
+	#[test]
+	fn replays_recorded_session() {
+		let path = session_path("replay");
+
+		let mut mock = MockDevice::new();
+		mock.respond(0x01, 0x0305, 0x1, &[0x05, 0x1a, 0x04, 0x00, 0x00, 0x00]);
+		let mut recording = RecordingTransport::new(mock);
+		crate::usb::write_config(&mut recording, &Config::default(), &TARGET_DEVICES[0]).unwrap();
+		let mut buf = [0u8; 6];
+		recording.claim_interface(1).unwrap();
+		recording
+			.read_control(0xa1, 0x01, 0x0305, 0x1, &mut buf, Duration::from_secs(1))
+			.unwrap();
+		recording.release_interface(1).unwrap();
+		recording.save(&path).unwrap();
+
+		let mut replay = ReplayTransport::open(&path);
+		crate::usb::write_config(&mut replay, &Config::default(), &TARGET_DEVICES[0]).unwrap();
+		let mut buf = [0u8; 6];
+		replay.claim_interface(1).unwrap();
+		replay
+			.read_control(0xa1, 0x01, 0x0305, 0x1, &mut buf, Duration::from_secs(1))
+			.unwrap();
+		replay.release_interface(1).unwrap();
+		assert_eq!(buf, [0x05, 0x1a, 0x04, 0x00, 0x00, 0x00]);
+		replay.finish();
+
+		let _ = fs::remove_file(&path);
+	}
+
+	#[test]
+	#[should_panic(expected = "replay mismatch")]
+	fn rejects_different_bytes() {
+		let path = session_path("mismatch");
+
+		let mut recording = RecordingTransport::new(MockDevice::new());
+		crate::usb::write_config(&mut recording, &Config::default(), &TARGET_DEVICES[0]).unwrap();
+		recording.save(&path).unwrap();
+
+		let mut replay = ReplayTransport::open(&path);
+		let _ = fs::remove_file(&path);
+		let _ = crate::usb::write_config(
+			&mut replay,
+			&Config {
+				debounce_time: crate::config::DebounceTime::_16ms,
+				..Config::default()
+			},
+			&TARGET_DEVICES[0],
+		);
+	}
+}
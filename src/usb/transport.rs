@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use rusb::{DeviceHandle, UsbContext};
+
+/// A link to a mouse capable of the operations needed to configure it.
+///
+/// [`RusbTransport`] talks to real hardware, while
+/// [`MockDevice`](super::mock::MockDevice) records everything sent to it.
+pub trait Transport {
+	/// Claims `interface`, detaching any kernel driver bound to it.
+	fn claim_interface(&mut self, interface: u8) -> rusb::Result<()>;
+
+	/// Releases `interface`, reattaching the kernel driver if one was
+	/// detached when it was claimed.
+	fn release_interface(&mut self, interface: u8) -> rusb::Result<()>;
+
+	/// Sends a control transfer to the device. Parameters match the fields
+	/// of the setup packet (see [`DeviceHandle::write_control`]).
+	fn write_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &[u8],
+		timeout: Duration,
+	) -> rusb::Result<usize>;
+
+	/// Reads a control transfer from the device. Parameters match the fields
+	/// of the setup packet (see [`DeviceHandle::read_control`]).
+	fn read_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &mut [u8],
+		timeout: Duration,
+	) -> rusb::Result<usize>;
+}
+
+/// [`Transport`] backed by an open libusb device handle.
+///
+/// Kernel drivers detached when claiming an interface are reattached when
+/// it is released, if claiming it fails, or when the transport is dropped
+/// with the interface still claimed.
+pub struct RusbTransport<T: UsbContext> {
+	handle: DeviceHandle<T>,
+	/// Interfaces which had a kernel driver detached when claimed
+	detached: Vec<u8>,
+}
+
+impl<T: UsbContext> RusbTransport<T> {
+	pub fn new(handle: DeviceHandle<T>) -> Self {
+		Self {
+			handle,
+			detached: Vec::new(),
+		}
+	}
+}
+
+impl<T: UsbContext> Transport for RusbTransport<T> {
+	fn claim_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		let detach =
+			rusb::supports_detach_kernel_driver() && self.handle.kernel_driver_active(interface)?;
+		if detach {
+			self.handle.detach_kernel_driver(interface)?;
+		}
+
+		match self.handle.claim_interface(interface) {
+			Ok(()) => {
+				if detach {
+					self.detached.push(interface);
+				}
+				Ok(())
+			}
+			Err(e) => {
+				if detach {
+					// the claim error is more useful than a reattach error
+					let _ = self.handle.attach_kernel_driver(interface);
+				}
+				Err(e)
+			}
+		}
+	}
+
+	fn release_interface(&mut self, interface: u8) -> rusb::Result<()> {
+		// the kernel driver is reattached even if releasing fails
+		let released = self.handle.release_interface(interface);
+
+		if let Some(i) = self.detached.iter().position(|&i| i == interface) {
+			self.detached.remove(i);
+			self.handle.attach_kernel_driver(interface)?;
+		}
+
+		released
+	}
+
+	fn write_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &[u8],
+		timeout: Duration,
+	) -> rusb::Result<usize> {
+		self.handle
+			.write_control(request_type, request, value, index, buf, timeout)
+	}
+
+	fn read_control(
+		&mut self,
+		request_type: u8,
+		request: u8,
+		value: u16,
+		index: u16,
+		buf: &mut [u8],
+		timeout: Duration,
+	) -> rusb::Result<usize> {
+		self.handle
+			.read_control(request_type, request, value, index, buf, timeout)
+	}
+}
+
+impl<T: UsbContext> Drop for RusbTransport<T> {
+	fn drop(&mut self) {
+		for interface in std::mem::take(&mut self.detached) {
+			let _ = self.handle.release_interface(interface);
+			if let Err(e) = self.handle.attach_kernel_driver(interface) {
+				eprintln!(
+					"warning: could not reattach kernel driver to interface {interface}: {e}"
+				);
+			}
+		}
+	}
+}